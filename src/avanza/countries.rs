@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+
+lazy_static! {
+    /// Swedish country names used by Avanza mapped to ISO 3166-1 alpha-2 codes
+    static ref SWEDISH_COUNTRY_NAMES: HashMap<&'static str, &'static str> = hashmap! {
+        "Sverige" => "SE",
+        "Danmark" => "DK",
+        "Norge" => "NO",
+        "Finland" => "FI",
+        "Island" => "IS",
+        "USA" => "US",
+        "Kanada" => "CA",
+        "Mexiko" => "MX",
+        "Brasilien" => "BR",
+        "Argentina" => "AR",
+        "Chile" => "CL",
+        "Colombia" => "CO",
+        "Peru" => "PE",
+        "Storbritannien" => "GB",
+        "Irland" => "IE",
+        "Tyskland" => "DE",
+        "Frankrike" => "FR",
+        "Nederländerna" => "NL",
+        "Belgien" => "BE",
+        "Luxemburg" => "LU",
+        "Schweiz" => "CH",
+        "Österrike" => "AT",
+        "Italien" => "IT",
+        "Spanien" => "ES",
+        "Portugal" => "PT",
+        "Grekland" => "GR",
+        "Polen" => "PL",
+        "Tjeckien" => "CZ",
+        "Ungern" => "HU",
+        "Estland" => "EE",
+        "Lettland" => "LV",
+        "Litauen" => "LT",
+        "Cypern" => "CY",
+        "Malta" => "MT",
+        "Ryssland" => "RU",
+        "Turkiet" => "TR",
+        "Israel" => "IL",
+        "Saudiarabien" => "SA",
+        "Förenade Arabemiraten" => "AE",
+        "Qatar" => "QA",
+        "Kuwait" => "KW",
+        "Sydafrika" => "ZA",
+        "Kina" => "CN",
+        "Hongkong" => "HK",
+        "Taiwan" => "TW",
+        "Japan" => "JP",
+        "Sydkorea" => "KR",
+        "Indien" => "IN",
+        "Indonesien" => "ID",
+        "Thailand" => "TH",
+        "Malaysia" => "MY",
+        "Filippinerna" => "PH",
+        "Vietnam" => "VN",
+        "Singapore" => "SG",
+        "Australien" => "AU",
+        "Nya Zeeland" => "NZ",
+        "Bermuda" => "BM",
+        "Caymanöarna" => "KY",
+        "Jersey" => "JE",
+        "Guernsey" => "GG",
+    };
+}
+
+/// ISO code for a country in Avanza's data, preferring the country code when Avanza has one.
+/// Returns None for entries that are not a country, e.g. "Övriga"
+pub fn iso_country_code(name: &str, country_code: &str) -> Option<String> {
+    let country_code = country_code.trim();
+    if country_code.len() == 2 && country_code.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Some(country_code.to_ascii_uppercase());
+    }
+    SWEDISH_COUNTRY_NAMES
+        .get(name.trim())
+        .map(|code| code.to_string())
+}
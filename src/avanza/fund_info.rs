@@ -27,7 +27,6 @@ pub struct AvanzaFundInfo {
     // pub development_three_years: f64,
     // pub development_five_years: f64,
     pub country_chart_data: Vec<CountryChartDaum>,
-    // pub holding_chart_data: Vec<HoldingChartDaum>,
     pub sector_chart_data: Vec<SectorChartDaum>,
     // pub low_carbon: bool,
     // pub index_fund: bool,
@@ -35,8 +34,6 @@ pub struct AvanzaFundInfo {
     // pub standard_deviation: f64,
     // pub capital: f64,
     // pub start_date: String,
-    // pub fund_managers: Vec<FundManager>,
-    // pub admin_company: AdminCompany,
     // pub pricing_frequency: String,
     // pub prospectus_link: String,
     // pub aum_covered_carbon: f64,
@@ -58,11 +55,9 @@ pub struct AvanzaFundInfo {
     // pub governance_score: f64,
     // pub controversy_score: Value,
     // pub carbon_solutions_involvement: f64,
-    // pub product_involvements: Vec<ProductInvolvement>,
     // pub sustainability_rating: i64,
     // pub sustainability_rating_category_name: String,
     // pub svanen: bool,
-    // pub fund_rating_views: Vec<FundRatingView>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -75,19 +70,6 @@ pub struct CountryChartDaum {
     pub country_code: String,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct HoldingChartDaum {
-    pub name: String,
-    pub y: f64,
-    #[serde(rename = "type")]
-    pub type_field: String,
-    pub currency: String,
-    pub country_code: String,
-    pub isin: String,
-    pub orderbook_id: Option<String>,
-}
-
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SectorChartDaum {
@@ -101,37 +83,6 @@ pub struct SectorChartDaum {
     // pub orderbook_id: Value,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct FundManager {
-    pub name: String,
-    pub start_date: String,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct AdminCompany {
-    pub name: String,
-    pub country: String,
-    pub url: String,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ProductInvolvement {
-    pub product: String,
-    pub product_description: String,
-    pub value: f64,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct FundRatingView {
-    pub date: String,
-    pub fund_rating_type: String,
-    pub fund_rating: i64,
-}
-
 lazy_static! {
     static ref CLIENT: reqwest::Client = reqwest::Client::new();
 }
@@ -1,7 +1,14 @@
-use std::clone;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use chrono::{Months, NaiveDate};
+use lazy_static::lazy_static;
+use log::debug;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::SymbolType;
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AvanzaHistory {
@@ -20,6 +27,84 @@ pub struct DataSerie {
     #[serde(rename = "y")]
     pub price: f64,
 }
+
+/// Price chart of exchange traded instruments, stocks, ETFs, certificates etc.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AvanzaPriceChart {
+    pub ohlc: Vec<Ohlc>,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ohlc {
+    pub timestamp: i64,
+    pub open: f64,
+    pub close: f64,
+    pub low: f64,
+    pub high: f64,
+    #[serde(default)]
+    pub total_volume_traded: i64,
+}
+
+/// Price series shared by funds and exchange traded instruments
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct PriceHistory {
+    /// YYYY-MM-DD
+    pub from_date: String,
+    pub prices: Vec<PricePoint>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct PricePoint {
+    /// Milliseconds since the epoch
+    pub timestamp: i64,
+    /// NAV for funds, closing price for everything else
+    pub price: f64,
+}
+
+impl From<AvanzaHistory> for PriceHistory {
+    fn from(history: AvanzaHistory) -> Self {
+        PriceHistory {
+            from_date: history.from_date,
+            prices: history
+                .data_serie
+                .into_iter()
+                .map(|data| PricePoint {
+                    timestamp: data.timestamp,
+                    price: data.price,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<AvanzaPriceChart> for PriceHistory {
+    fn from(chart: AvanzaPriceChart) -> Self {
+        PriceHistory {
+            from_date: chart.from,
+            prices: chart
+                .ohlc
+                .into_iter()
+                .map(|ohlc| PricePoint {
+                    timestamp: ohlc.timestamp,
+                    price: ohlc.close,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Price of an instrument on a trading day
+#[derive(Debug, Clone, PartialEq)]
+pub struct DailyPrice {
+    pub date: NaiveDate,
+    pub price: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum TimePeriod {
     OneMonth,
     ThreeMonths,
@@ -39,12 +124,298 @@ impl TimePeriod {
             Self::Max => "max".to_string(),
         }
     }
+
+    /// Shortest period that reaches back from `today` to `from`
+    pub fn covering(from: NaiveDate, today: NaiveDate) -> TimePeriod {
+        let periods = [
+            (1, Self::OneMonth),
+            (3, Self::ThreeMonths),
+            (12, Self::OneYear),
+            (36, Self::ThreeYears),
+            (60, Self::FiveYears),
+        ];
+        periods
+            .into_iter()
+            .find(|(months, _)| {
+                today
+                    .checked_sub_months(Months::new(*months))
+                    .is_some_and(|start| start <= from)
+            })
+            .map_or(Self::Max, |(_, period)| period)
+    }
+
+    fn longer(&self) -> Option<TimePeriod> {
+        match self {
+            Self::OneMonth => Some(Self::ThreeMonths),
+            Self::ThreeMonths => Some(Self::OneYear),
+            Self::OneYear => Some(Self::ThreeYears),
+            Self::ThreeYears => Some(Self::FiveYears),
+            Self::FiveYears => Some(Self::Max),
+            Self::Max => None,
+        }
+    }
+
+    /// The price chart names the full history `infinity`
+    fn to_price_chart_str(&self) -> String {
+        match self {
+            Self::Max => "infinity".to_string(),
+            _ => self.to_str(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+/// Daily prices for the period, from the fund chart for funds and the price chart otherwise
+pub async fn get_price_history(
+    symbol_type: &SymbolType,
+    orderbook_id: &str,
+    time_period: &TimePeriod,
+) -> Result<PriceHistory, Box<dyn Error>> {
+    match symbol_type {
+        SymbolType::MUTUALFUND => Ok(get_history(orderbook_id, time_period).await?.into()),
+        SymbolType::STOCK => Ok(get_price_chart(orderbook_id, time_period).await?.into()),
+    }
+}
+
+pub async fn get_history(
+    orderbook_id: &str,
+    time_period: &TimePeriod,
+) -> Result<AvanzaHistory, Box<dyn Error>> {
+    let url = format!(
+        "https://www.avanza.se/_api/fund-guide/chart/{}/{}?raw=true",
+        orderbook_id,
+        time_period.to_str()
+    );
+    let response = CLIENT.get(&url).send().await?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to get fund chart: {}", response.text().await?).into());
+    }
+    Ok(response.json().await?)
+}
+
+pub async fn get_price_chart(
+    orderbook_id: &str,
+    time_period: &TimePeriod,
+) -> Result<AvanzaPriceChart, Box<dyn Error>> {
+    let url = format!(
+        "https://www.avanza.se/_api/price-chart/stock/{}?timePeriod={}&resolution=day",
+        orderbook_id,
+        time_period.to_price_chart_str()
+    );
+    let response = CLIENT.get(&url).send().await?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to get price chart: {}", response.text().await?).into());
+    }
+    Ok(response.json().await?)
+}
+
+/// Prices for every trading day in [from, to]. The shortest period covering `from` is fetched,
+/// a longer one only if Avanza's series turns out not to reach back far enough
+pub async fn get_daily_prices(
+    symbol_type: &SymbolType,
+    orderbook_id: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<DailyPrice>, Box<dyn Error>> {
+    let today = chrono::offset::Local::now().date_naive();
+    let mut time_period = TimePeriod::covering(from, today);
+    loop {
+        debug!("Fetching price history for period {}", time_period.to_str());
+        let history = get_price_history(symbol_type, orderbook_id, &time_period).await?;
+        match time_period.longer() {
+            Some(longer) if !reaches(&history, from) => time_period = longer,
+            _ => return Ok(daily_prices(history.prices, from, to)),
+        }
+    }
+}
+
+fn reaches(history: &PriceHistory, from: NaiveDate) -> bool {
+    let first_date = NaiveDate::parse_from_str(&history.from_date, "%Y-%m-%d")
+        .ok()
+        .or_else(|| trading_date(history.prices.first()?.timestamp));
+    first_date.is_some_and(|date| date <= from) && !history.prices.is_empty()
+}
+
+/// One price per date within [from, to] in ascending order, the latest point wins for a date
+pub fn daily_prices(
+    mut prices: Vec<PricePoint>,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Vec<DailyPrice> {
+    prices.sort_by_key(|point| point.timestamp);
+    let mut by_date: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+    for point in prices {
+        if let Some(date) = trading_date(point.timestamp) {
+            by_date.insert(date, point.price);
+        }
+    }
+    by_date
+        .range(from..=to)
+        .map(|(date, price)| DailyPrice {
+            date: *date,
+            price: *price,
+        })
+        .collect()
+}
+
+/// Trading date of a daily point. Avanza stamps these at midnight, e.g. 2024-06-03 00:00 in
+/// Stockholm is 2024-06-02 22:00 UTC. Midnight anywhere within 12 hours of UTC lands on the right
+/// date when shifted 12 hours, so the date does not depend on which zone the stamp is in
+fn trading_date(timestamp: i64) -> Option<NaiveDate> {
+    chrono::DateTime::from_timestamp_millis(timestamp + 12 * 60 * 60 * 1000)
+        .map(|date| date.date_naive())
 }
 
-pub async fn get_history(orderbook_id: &str, time_period: &TimePeriod) -> AvanzaHistory {
-    let url  = format!("https://www.avanza.se/_api/fund-guide/chart/{}/{}?raw=true", orderbook_id, time_period.to_str());
-    let client = reqwest::Client::new();
-    let response = client.get(&url).send().await.unwrap();
-    let parsed_response: AvanzaHistory = response.json().await.unwrap();
-    parsed_response
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture<T: serde::de::DeserializeOwned>(json: &str) -> T {
+        serde_json::from_str(json).expect("Failed to parse fixture")
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn one_month() -> PriceHistory {
+        fixture::<AvanzaHistory>(include_str!(
+            "../../tests/fixtures/fund_chart_one_month.json"
+        ))
+        .into()
+    }
+
+    fn three_months() -> PriceHistory {
+        fixture::<AvanzaHistory>(include_str!(
+            "../../tests/fixtures/fund_chart_three_months.json"
+        ))
+        .into()
+    }
+
+    #[test]
+    fn picks_shortest_covering_period() {
+        let today = date("2024-05-31");
+        assert_eq!(
+            TimePeriod::covering(date("2024-05-20"), today),
+            TimePeriod::OneMonth
+        );
+        assert_eq!(
+            TimePeriod::covering(date("2024-04-30"), today),
+            TimePeriod::OneMonth
+        );
+        assert_eq!(
+            TimePeriod::covering(date("2024-04-29"), today),
+            TimePeriod::ThreeMonths
+        );
+        assert_eq!(
+            TimePeriod::covering(date("2023-06-01"), today),
+            TimePeriod::OneYear
+        );
+        assert_eq!(
+            TimePeriod::covering(date("2021-06-01"), today),
+            TimePeriod::ThreeYears
+        );
+        assert_eq!(
+            TimePeriod::covering(date("2020-01-01"), today),
+            TimePeriod::FiveYears
+        );
+        assert_eq!(
+            TimePeriod::covering(date("2010-01-01"), today),
+            TimePeriod::Max
+        );
+    }
+
+    #[test]
+    fn overlapping_series_are_deduplicated_and_sorted() {
+        let mut prices = one_month().prices;
+        prices.extend(three_months().prices);
+        prices.reverse();
+
+        let daily = daily_prices(prices, date("2000-01-01"), date("2100-01-01"));
+
+        assert_eq!(daily.len(), three_months().prices.len());
+        assert!(daily.windows(2).all(|pair| pair[0].date < pair[1].date));
+    }
+
+    #[test]
+    fn only_prices_in_range_are_kept() {
+        let daily = daily_prices(
+            three_months().prices,
+            date("2024-01-06"),
+            date("2024-01-12"),
+        );
+
+        assert_eq!(
+            daily
+                .iter()
+                .map(|price| price.date)
+                .collect::<Vec<NaiveDate>>(),
+            vec![
+                date("2024-01-08"),
+                date("2024-01-09"),
+                date("2024-01-10"),
+                date("2024-01-11"),
+                date("2024-01-12"),
+            ]
+        );
+    }
+
+    #[test]
+    fn range_bounds_are_inclusive() {
+        let daily = daily_prices(one_month().prices, date("2024-01-02"), date("2024-01-31"));
+
+        assert_eq!(daily.len(), one_month().prices.len());
+        assert_eq!(daily.first().unwrap().date, date("2024-01-02"));
+        assert_eq!(daily.last().unwrap().date, date("2024-01-31"));
+    }
+
+    #[test]
+    fn midnight_stamps_keep_their_date() {
+        // 2024-01-02 00:00 CET and 2024-06-03 00:00 CEST, the previous day in UTC
+        assert_eq!(trading_date(1704150000000), Some(date("2024-01-02")));
+        assert_eq!(trading_date(1717365600000), Some(date("2024-06-03")));
+        // 2024-06-03 00:00 EDT, a US listing
+        assert_eq!(trading_date(1717387200000), Some(date("2024-06-03")));
+    }
+
+    #[test]
+    fn empty_series_gives_no_prices() {
+        let history: PriceHistory =
+            fixture::<AvanzaHistory>(include_str!("../../tests/fixtures/fund_chart_empty.json"))
+                .into();
+
+        assert!(!reaches(&history, date("2024-01-15")));
+        assert!(daily_prices(history.prices, date("2024-01-01"), date("2024-01-31")).is_empty());
+    }
+
+    #[test]
+    fn price_chart_uses_closing_prices() {
+        let chart: AvanzaPriceChart = fixture(include_str!(
+            "../../tests/fixtures/price_chart_one_month.json"
+        ));
+        let closes: Vec<f64> = chart.ohlc.iter().map(|ohlc| ohlc.close).collect();
+
+        let history: PriceHistory = chart.into();
+
+        assert_eq!(history.from_date, "2024-01-01");
+        assert_eq!(
+            history
+                .prices
+                .iter()
+                .map(|point| point.price)
+                .collect::<Vec<f64>>(),
+            closes
+        );
+    }
+
+    #[test]
+    fn series_starting_after_from_does_not_reach() {
+        let history = one_month();
+
+        assert!(reaches(&history, date("2024-01-15")));
+        assert!(!reaches(&history, date("2023-12-15")));
+    }
+}
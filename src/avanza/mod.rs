@@ -1,5 +1,8 @@
+pub(crate) mod countries;
 pub mod fund_info;
 pub(crate) mod history;
 pub(crate) mod search;
 pub mod stock_info;
+pub(crate) mod swedish_format;
+pub(crate) mod symbol_resolver;
 pub(crate) mod transaction_history_parser;
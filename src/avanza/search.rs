@@ -1,11 +1,10 @@
 use std::error::Error;
 
 use lazy_static::lazy_static;
-use serde_json::{json, Value};
+use serde_json::json;
 
 use serde::{Deserialize, Serialize};
 use log::info;
-use crate::SymbolType;
 
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -13,10 +12,8 @@ use crate::SymbolType;
 pub struct AvanzaSearchResult {
     // pub total_number_of_hits: i64,
     pub result_groups: Vec<ResultGroup>,
-    // pub page_search_results: PageSearchResults,
     // pub search_query: String,
     // pub url_encoded_search_query: String,
-    // pub configuration_response: ConfigurationResponse,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -58,20 +55,6 @@ pub struct Link {
     pub short_link_display: String,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct PageSearchResults {
-    pub total_number_of_hits: i64,
-    pub number_of_hits: i64,
-    pub hits: Vec<Value>,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ConfigurationResponse {
-    pub monthly_savings_url: String,
-}
-
 lazy_static! {
     static ref CLIENT: reqwest::Client = reqwest::Client::new();
 }
@@ -2,7 +2,6 @@ use std::error::Error;
 
 use lazy_static::lazy_static;
 
-
 use serde::{Deserialize, Serialize};
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -13,6 +12,16 @@ pub struct AvanzaStockInfo {
     pub instrument_id: String,
     pub quote: Quote,
     pub listing: Listing,
+    /// Sector first, followed by the more specific industries
+    #[serde(default)]
+    pub sectors: Vec<Sector>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sector {
+    pub sector_id: String,
+    pub sector_name: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -20,6 +29,8 @@ pub struct AvanzaStockInfo {
 pub struct Listing {
     pub ticker_symbol: String,
     pub currency: String,
+    #[serde(default)]
+    pub country_code: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -0,0 +1,141 @@
+use std::str::FromStr;
+
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+const THOUSANDS_SEPARATORS: [char; 3] = [' ', '\u{a0}', '\u{202f}'];
+
+/// Parses a number as written in Avanza's exports, e.g. `-1 234,56`. Spaces, including
+/// non-breaking ones, separate groups of three digits and `,` is the decimal separator. A
+/// Unicode minus and a trailing currency such as `SEK` are accepted, `-` alone means zero.
+/// An empty value is an error
+pub fn parse_decimal(s: &str) -> Result<Decimal, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("empty value".to_string());
+    }
+    if s == "-" {
+        return Ok(Decimal::ZERO);
+    }
+    let s = strip_currency_suffix(s);
+    let (negative, s) = match s.strip_prefix(['-', '\u{2212}']) {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    if let Some(c) = s
+        .chars()
+        .find(|c| !c.is_ascii_digit() && *c != ',' && !THOUSANDS_SEPARATORS.contains(c))
+    {
+        return Err(match c {
+            '.' => "unexpected '.', the decimal separator is ','".to_string(),
+            _ => format!("unexpected character {:?}", c),
+        });
+    }
+    let (integer, fraction) = match s.split_once(',') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (s, None),
+    };
+    let groups: Vec<&str> = integer.split(THOUSANDS_SEPARATORS).collect();
+    if groups.iter().any(|group| group.is_empty()) {
+        return Err("not a number".to_string());
+    }
+    if groups.len() > 1 && (groups[0].len() > 3 || groups[1..].iter().any(|g| g.len() != 3)) {
+        return Err(format!("bad thousands grouping in {:?}", integer));
+    }
+    let mut normalized = String::with_capacity(s.len() + 1);
+    if negative {
+        normalized.push('-');
+    }
+    normalized.push_str(&groups.concat());
+    if let Some(fraction) = fraction {
+        if fraction.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return Err("bad decimals, expected digits after ','".to_string());
+        }
+        normalized.push('.');
+        normalized.push_str(fraction);
+    }
+    Decimal::from_str(&normalized).map_err(|e| e.to_string())
+}
+
+fn strip_currency_suffix(s: &str) -> &str {
+    let number = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let suffix_length = s.len() - number.len();
+    if (2..=3).contains(&suffix_length) {
+        number.trim_end()
+    } else {
+        s
+    }
+}
+
+/// Parses a date in the `YYYY-MM-DD` format used by Avanza
+pub fn parse_date(s: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d")
+        .map_err(|e| format!("{}, expected YYYY-MM-DD", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(s: &str) -> Decimal {
+        Decimal::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn parses_plain_numbers() {
+        assert_eq!(parse_decimal("12"), Ok(decimal("12")));
+        assert_eq!(parse_decimal("-0,5"), Ok(decimal("-0.5")));
+        assert_eq!(parse_decimal("+3,25"), Ok(decimal("3.25")));
+        assert_eq!(parse_decimal("-"), Ok(Decimal::ZERO));
+    }
+
+    #[test]
+    fn accepts_all_thousands_separators() {
+        assert_eq!(parse_decimal("1 234,56"), Ok(decimal("1234.56")));
+        assert_eq!(parse_decimal("1\u{a0}234,56"), Ok(decimal("1234.56")));
+        assert_eq!(
+            parse_decimal("12\u{202f}345\u{202f}678"),
+            Ok(decimal("12345678"))
+        );
+    }
+
+    #[test]
+    fn accepts_unicode_minus() {
+        assert_eq!(parse_decimal("\u{2212}1 234,5"), Ok(decimal("-1234.5")));
+    }
+
+    #[test]
+    fn strips_currency_suffix() {
+        assert_eq!(parse_decimal("1 234,56 SEK"), Ok(decimal("1234.56")));
+        assert_eq!(parse_decimal("-99,9 USD"), Ok(decimal("-99.9")));
+        assert!(parse_decimal("12 ABCD").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_grouping() {
+        assert!(parse_decimal("12 34 5").is_err());
+        assert!(parse_decimal("1 2,3").is_err());
+        assert!(parse_decimal("1234 567").is_err());
+        assert!(parse_decimal("1  234").is_err());
+    }
+
+    #[test]
+    fn rejects_empty_and_malformed_input() {
+        assert!(parse_decimal("").is_err());
+        assert!(parse_decimal("   ").is_err());
+        assert!(parse_decimal("1.5").is_err());
+        assert!(parse_decimal("1,").is_err());
+        assert!(parse_decimal("1,2,3").is_err());
+        assert!(parse_decimal(",5").is_err());
+        assert!(parse_decimal("--1").is_err());
+    }
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(
+            parse_date("2024-01-31"),
+            Ok(NaiveDate::from_ymd_opt(2024, 1, 31).unwrap())
+        );
+        assert!(parse_date("31/01/2024").is_err());
+    }
+}
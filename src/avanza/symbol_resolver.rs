@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use inquire::{Select, Text};
+
+use crate::avanza::search::{search_avanza, Hit};
+use crate::avanza::stock_info::avanza_get_stock_info;
+use crate::config::Config;
+use crate::{format_hit, SymbolType};
+
+const DATA_SOURCES: [&str; 2] = ["YAHOO", "MANUAL"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GhostfolioSymbol {
+    pub symbol: String,
+    pub data_source: String,
+    /// The Avanza instrument the symbol was resolved from, if any
+    pub hit: Option<Hit>,
+}
+
+/// Resolves ISINs to the symbol and data source used in Ghostfolio. Known mappings are read
+/// from `Config.avanza_to_ghostfolio_ticker`, keyed on the Avanza orderbook id, or from
+/// `Config.isin_to_ghostfolio_symbol` for instruments Avanza does not find. New ones are asked
+/// for and stored in the config
+#[derive(Default)]
+pub struct SymbolResolver {
+    resolved: HashMap<String, GhostfolioSymbol>,
+}
+
+impl SymbolResolver {
+    pub fn new() -> SymbolResolver {
+        SymbolResolver::default()
+    }
+
+    /// Fails when Avanza can not be searched, a missing instrument is only assumed when the
+    /// search succeeds without hits
+    pub async fn resolve(
+        &mut self,
+        config: &mut Config,
+        isin: &str,
+    ) -> Result<GhostfolioSymbol, Box<dyn Error>> {
+        if let Some(symbol) = self.resolved.get(isin) {
+            return Ok(symbol.clone());
+        }
+        if let Some(symbol) = config.isin_to_ghostfolio_symbol.get(isin) {
+            return Ok(GhostfolioSymbol {
+                symbol: symbol.clone(),
+                data_source: config
+                    .isin_to_ghostfolio_data_source
+                    .get(isin)
+                    .cloned()
+                    .unwrap_or("MANUAL".to_string()),
+                hit: None,
+            });
+        }
+        let hits = search_avanza(&isin.to_string())
+            .await
+            .map_err(|e| format!("Failed to search Avanza for {}: {}", isin, e))?;
+        let symbol = if !hits.is_empty() {
+            let hit = select_hit(config, isin, &hits);
+            resolve_hit(config, hit).await
+        } else {
+            println!("No Avanza instrument found for {}", isin);
+            let symbol = prompt_symbol(isin, isin.to_string(), "MANUAL");
+            config
+                .isin_to_ghostfolio_symbol
+                .insert(isin.to_string(), symbol.symbol.clone());
+            config
+                .isin_to_ghostfolio_data_source
+                .insert(isin.to_string(), symbol.data_source.clone());
+            symbol
+        };
+        self.resolved.insert(isin.to_string(), symbol.clone());
+        Ok(symbol)
+    }
+}
+
+/// Prefers a hit that is already mapped so that known instruments resolve without prompting
+pub(crate) fn select_hit<'a>(config: &Config, isin: &str, hits: &'a [Hit]) -> &'a Hit {
+    if let Some(hit) = hits.iter().find(|hit| {
+        config
+            .avanza_to_ghostfolio_ticker
+            .contains_key(&hit.link.orderbook_id)
+    }) {
+        return hit;
+    }
+    if hits.len() == 1 {
+        return &hits[0];
+    }
+    let options = hits.iter().map(format_hit).collect::<Vec<String>>();
+    let ans: String = Select::new(&format!("Select instrument for {}", isin), options.clone())
+        .prompt()
+        .expect("Failed to get input");
+    let index = options.iter().position(|x| *x == ans).unwrap();
+    &hits[index]
+}
+
+async fn resolve_hit(config: &mut Config, hit: &Hit) -> GhostfolioSymbol {
+    let orderbook_id = &hit.link.orderbook_id;
+    if let Some(symbol) = config.avanza_to_ghostfolio_ticker.get(orderbook_id) {
+        return GhostfolioSymbol {
+            symbol: symbol.clone(),
+            hit: Some(hit.clone()),
+            data_source: config
+                .avanza_to_ghostfolio_data_source
+                .get(orderbook_id)
+                .cloned()
+                .unwrap_or("MANUAL".to_string()),
+        };
+    }
+
+    let mut symbol = match hit.link.type_field.parse::<SymbolType>() {
+        Ok(SymbolType::STOCK) => match avanza_get_stock_info(orderbook_id).await {
+            Ok(stock_info) => prompt_symbol(
+                &hit.link.link_display,
+                yahoo_ticker(
+                    &stock_info.listing.ticker_symbol,
+                    &stock_info.listing.currency,
+                ),
+                "YAHOO",
+            ),
+            Err(_) => prompt_symbol(&hit.link.link_display, String::new(), "YAHOO"),
+        },
+        _ => prompt_symbol(&hit.link.link_display, orderbook_id.clone(), "MANUAL"),
+    };
+    config
+        .avanza_to_ghostfolio_ticker
+        .insert(orderbook_id.clone(), symbol.symbol.clone());
+    config
+        .avanza_to_ghostfolio_data_source
+        .insert(orderbook_id.clone(), symbol.data_source.clone());
+    symbol.hit = Some(hit.clone());
+    symbol
+}
+
+/// Yahoo lists Stockholm shares as e.g. `VOLV-B.ST` for Avanza's `VOLV B`
+fn yahoo_ticker(ticker_symbol: &str, currency: &str) -> String {
+    let ticker = ticker_symbol.replace(' ', "-");
+    if currency == "SEK" {
+        format!("{}.ST", ticker)
+    } else {
+        ticker
+    }
+}
+
+fn prompt_symbol(name: &str, suggestion: String, data_source: &str) -> GhostfolioSymbol {
+    let starting_cursor = DATA_SOURCES
+        .iter()
+        .position(|x| *x == data_source)
+        .unwrap_or(0);
+    let data_source = Select::new(
+        &format!("Select Ghostfolio data source for {}", name),
+        DATA_SOURCES.to_vec(),
+    )
+    .with_starting_cursor(starting_cursor)
+    .prompt()
+    .expect("Failed to get input");
+    let symbol = Text::new(&format!("Enter Ghostfolio symbol for {}", name))
+        .with_default(&suggestion)
+        .prompt()
+        .expect("Failed to get input");
+    GhostfolioSymbol {
+        symbol,
+        data_source: data_source.to_string(),
+        hit: None,
+    }
+}
@@ -1,13 +1,20 @@
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::path::{Path, PathBuf};
 
-use maplit::hashmap;
-use serde::Serialize;
+use chrono::NaiveDate;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 
-use crate::ghostfolio::GhostfolioApi;
+use crate::avanza::swedish_format::{parse_date, parse_decimal};
+use crate::avanza::symbol_resolver::{GhostfolioSymbol, SymbolResolver};
+use crate::ghostfolio::{Activity, ActivityImport, AssetQuery, GhostfolioApi, Order};
+use crate::ledger::Ledger;
 
-// Datum;Konto;Typ av transaktion;Värdepapper/beskrivning;Antal;Kurs;Belopp;Courtage;Valuta;ISIN;Resultat
-#[derive(Debug, PartialEq, Serialize)]
-enum GhostfolioType {
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GhostfolioType {
     BUY,
     DIVIDEND,
     FEE,
@@ -17,100 +24,1198 @@ enum GhostfolioType {
     SELL,
     OTHER,
 }
-impl GhostfolioType {
+
+/// The "Typ av transaktion" column of an Avanza export
+#[derive(Debug, Clone, PartialEq)]
+enum AvanzaTransactionType {
+    Buy,
+    Sell,
+    Dividend,
+    FundDistribution,
+    Interest,
+    InterestCompensation,
+    ForeignTax,
+    PreliminaryTax,
+    YieldTax,
+    Exchange,
+    Split,
+    Allotment,
+    Issue,
+    Redemption,
+    Other,
+    Deposit,
+    Withdrawal,
+    SecuritiesTransfer,
+    TransferFrom,
+    TransferTo,
+    Unknown(String),
+}
+
+impl AvanzaTransactionType {
     pub fn from_avanza(s: &str) -> Self {
         match s {
-            "Köp" => Self::BUY,
-            "Utdelning" => Self::DIVIDEND,
-            "Sälj" => Self::SELL,
-            "Utländsk källskatt" | "Preliminärskatt" => Self::FEE,
-            "Övrigt" => Self::OTHER,
-            "Ränta" => Self::INTEREST,
-            _ => panic!("Unknown ghostfolio type {}", s),
+            "Köp" => Self::Buy,
+            "Sälj" => Self::Sell,
+            "Utdelning" => Self::Dividend,
+            "Fondutdelning" => Self::FundDistribution,
+            "Ränta" => Self::Interest,
+            "Räntekompensation" => Self::InterestCompensation,
+            "Utländsk källskatt" => Self::ForeignTax,
+            "Preliminärskatt" => Self::PreliminaryTax,
+            "Avkastningsskatt" => Self::YieldTax,
+            "Byte" => Self::Exchange,
+            "Split" => Self::Split,
+            "Tilldelning" => Self::Allotment,
+            "Emission" => Self::Issue,
+            "Inlösen" => Self::Redemption,
+            "Övrigt" => Self::Other,
+            "Insättning" => Self::Deposit,
+            "Uttag" => Self::Withdrawal,
+            "Värdepappersöverföring" => Self::SecuritiesTransfer,
+            _ if s.starts_with("Övf från") => Self::TransferFrom,
+            _ if s.starts_with("Övf till") => Self::TransferTo,
+            _ => Self::Unknown(s.to_string()),
         }
     }
+
+    /// Ghostfolio type for the transaction, None when it can not be imported as an activity.
+    /// Corporate actions moving shares in or out are decided by the sign of the quantity
+    pub fn to_ghostfolio(&self, quantity: Decimal) -> Option<GhostfolioType> {
+        match self {
+            Self::Buy => Some(GhostfolioType::BUY),
+            Self::Sell => Some(GhostfolioType::SELL),
+            Self::Dividend | Self::FundDistribution => Some(GhostfolioType::DIVIDEND),
+            Self::Interest | Self::InterestCompensation => Some(GhostfolioType::INTEREST),
+            Self::ForeignTax | Self::PreliminaryTax | Self::YieldTax => Some(GhostfolioType::FEE),
+            Self::Exchange | Self::Allotment | Self::Issue | Self::Redemption => {
+                if quantity > Decimal::ZERO {
+                    Some(GhostfolioType::BUY)
+                } else if quantity < Decimal::ZERO {
+                    Some(GhostfolioType::SELL)
+                } else {
+                    None
+                }
+            }
+            // Split legs are turned into activities once both legs are paired, see `apply_splits`
+            Self::Split | Self::Other => Some(GhostfolioType::OTHER),
+            Self::Deposit
+            | Self::Withdrawal
+            | Self::SecuritiesTransfer
+            | Self::TransferFrom
+            | Self::TransferTo
+            | Self::Unknown(_) => None,
+        }
+    }
+}
+
+fn ghostfolio_date(date: NaiveDate) -> String {
+    format!("{}T00:00:00.000Z", date.format("%Y-%m-%d"))
+}
+
+fn to_f64(d: Decimal) -> f64 {
+    d.to_f64().expect("Decimal out of f64 range")
 }
-fn transform_avanza_number_to_number(s: &str) -> f64 {
-    if s == "-" {
-        return 0.0;
+
+/// A value in the export that could not be parsed
+#[derive(Debug)]
+pub struct ParseError {
+    row: u64,
+    column: String,
+    value: String,
+    message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Row {}, column {}: {} ({:?})",
+            self.row, self.column, self.message, self.value
+        )
     }
-    let s = s.replace(",", ".");
-    s.parse::<f64>().unwrap_or(0.0)
 }
 
-#[derive(Debug, Serialize)]
+impl Error for ParseError {}
+
+#[derive(Debug)]
 struct Record {
-    date: String,
+    date: NaiveDate,
+    avanza_account: String,
+    /// Ghostfolio account id, set by `map_accounts`
     account: String,
+    avanza_type: AvanzaTransactionType,
     transaction_type: GhostfolioType,
     security: String,
-    amount: f64,
-    pricePerUnit: f64,
-    price: f64,
-    fee: f64,
-    currency: String,
+    amount: Decimal,
+    price_per_unit: Decimal,
+    price: Decimal,
+    fee: Decimal,
+    /// Currency of `price_per_unit`
+    instrument_currency: String,
+    /// Currency of `price` and `fee`
+    settlement_currency: String,
+    /// Exchange rate from instrument to settlement currency when the export includes it
+    fx_rate: Option<Decimal>,
     isin: String,
-    result: f64,
+    fingerprint: String,
+    ghostfolio_symbol: Option<GhostfolioSymbol>,
+}
+
+/// Column positions in an Avanza export, found by header name. Known layouts:
+///
+/// `Datum;Konto;Typ av transaktion;Värdepapper/beskrivning;Antal;Kurs;Belopp;Courtage;Valuta;ISIN;Resultat`
+///
+/// `Datum;Konto;Typ av transaktion;Värdepapper/beskrivning;Antal;Kurs;Belopp;Transaktionsvaluta;Courtage (SEK);Växlingskurs;Instrumentvaluta;ISIN;Resultat`
+pub struct ColumnLayout {
+    headers: Vec<String>,
+    date: usize,
+    account: usize,
+    transaction_type: usize,
+    security: usize,
+    quantity: usize,
+    price_per_unit: usize,
+    amount: usize,
+    fee: usize,
+    instrument_currency: usize,
+    isin: usize,
+    settlement_currency: Option<usize>,
+    fx_rate: Option<usize>,
+}
+
+impl ColumnLayout {
+    pub fn from_headers(headers: &csv::StringRecord) -> Result<Self, Box<dyn Error>> {
+        let headers: Vec<&str> = headers
+            .iter()
+            .map(|header| header.trim_start_matches('\u{feff}').trim())
+            .collect();
+        // Names are in order of preference, e.g. "Instrumentvaluta" over "Valuta"
+        let find = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| headers.iter().position(|h| h == name))
+        };
+        let mut missing: Vec<String> = Vec::new();
+        let mut required = |names: &[&str]| {
+            find(names).unwrap_or_else(|| {
+                missing.push(names.join(" or "));
+                0
+            })
+        };
+        let layout = ColumnLayout {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            date: required(&["Datum"]),
+            account: required(&["Konto"]),
+            transaction_type: required(&["Typ av transaktion"]),
+            security: required(&["Värdepapper/beskrivning"]),
+            quantity: required(&["Antal"]),
+            price_per_unit: required(&["Kurs"]),
+            amount: required(&["Belopp"]),
+            fee: required(&["Courtage", "Courtage (SEK)"]),
+            instrument_currency: required(&["Instrumentvaluta", "Valuta"]),
+            isin: required(&["ISIN"]),
+            settlement_currency: find(&["Transaktionsvaluta"]),
+            fx_rate: find(&["Växlingskurs", "Valutakurs"]),
+        };
+        if missing.is_empty() {
+            Ok(layout)
+        } else {
+            Err(format!(
+                "Missing required columns: {} (found: {})",
+                missing.join(", "),
+                headers.join(";")
+            )
+            .into())
+        }
+    }
+}
+
+fn field(record: &csv::StringRecord, index: usize) -> &str {
+    record.get(index).unwrap_or("").trim()
+}
+
+impl ColumnLayout {
+    fn error(&self, record: &csv::StringRecord, index: usize, message: String) -> ParseError {
+        ParseError {
+            row: record.position().map_or(0, |p| p.line()),
+            column: self.headers[index].clone(),
+            value: field(record, index).to_string(),
+            message,
+        }
+    }
+
+    fn decimal(&self, record: &csv::StringRecord, index: usize) -> Result<Decimal, ParseError> {
+        parse_decimal(field(record, index)).map_err(|e| self.error(record, index, e))
+    }
+
+    fn date(&self, record: &csv::StringRecord, index: usize) -> Result<NaiveDate, ParseError> {
+        parse_date(field(record, index)).map_err(|e| self.error(record, index, e))
+    }
 }
 
 impl Record {
-    pub async fn from_csv_record(record: csv::StringRecord) -> Self {
-        let mut ghostfolio: GhostfolioApi = GhostfolioApi::new();
-        let avanza_account = record.get(1).unwrap();
+    pub fn from_csv_record(
+        record: csv::StringRecord,
+        columns: &ColumnLayout,
+    ) -> Result<Self, ParseError> {
+        let date = columns.date(&record, columns.date)?;
+        let amount = columns.decimal(&record, columns.quantity)?;
+        let price = columns.decimal(&record, columns.amount)?;
+        let fee = columns.decimal(&record, columns.fee)?;
+        let avanza_account = field(&record, columns.account);
+        let fingerprint = [
+            field(&record, columns.date),
+            avanza_account,
+            field(&record, columns.transaction_type),
+            field(&record, columns.isin),
+            &amount.normalize().to_string(),
+            &price.normalize().to_string(),
+            &fee.normalize().to_string(),
+        ]
+        .join("|");
+
+        let avanza_type =
+            AvanzaTransactionType::from_avanza(field(&record, columns.transaction_type));
+        // The rate is left empty on rows without a currency exchange
+        let fx_rate = match columns.fx_rate {
+            Some(i) if field(&record, i).trim().is_empty() => None,
+            Some(i) => Some(columns.decimal(&record, i)?).filter(|rate| !rate.is_zero()),
+            None => None,
+        };
 
         let mut record = Record {
-            date: record.get(0).unwrap().to_string(),
-            account: ghostfolio
-                .get_account_mapping(avanza_account.to_string())
-                .await
+            date,
+            avanza_account: avanza_account.to_string(),
+            account: String::new(),
+            transaction_type: avanza_type
+                .to_ghostfolio(amount)
+                .unwrap_or(GhostfolioType::OTHER),
+            avanza_type,
+            security: field(&record, columns.security).to_string(),
+            amount,
+            price_per_unit: columns.decimal(&record, columns.price_per_unit)?,
+            price,
+            fee,
+            instrument_currency: field(&record, columns.instrument_currency).to_string(),
+            settlement_currency: columns
+                .settlement_currency
+                .map(|i| field(&record, i))
+                .filter(|currency| !currency.is_empty())
+                .unwrap_or("SEK")
                 .to_string(),
-            transaction_type: GhostfolioType::from_avanza(record.get(2).unwrap()),
-            security: record.get(3).unwrap().to_string(),
-            amount: transform_avanza_number_to_number(record.get(4).unwrap()),
-            pricePerUnit: transform_avanza_number_to_number(record.get(5).unwrap()),
-            price: transform_avanza_number_to_number(record.get(6).unwrap()),
-            fee: transform_avanza_number_to_number(record.get(7).unwrap()),
-            currency: record.get(8).unwrap().to_string(),
-            isin: record.get(9).unwrap().to_string(),
-            result: transform_avanza_number_to_number(record.get(10).unwrap()),
-        };
-        if record.transaction_type == GhostfolioType::OTHER {
-            if record.amount == 0.0 {
-                // Probably dividend, We have two entries one where amount positive and one where
-                // ngative
-                if record.amount > 0.0 {
-                    record.transaction_type = GhostfolioType::BUY;
-                } else {
-                    record.transaction_type = GhostfolioType::SELL;
-                    record.amount = record.amount.abs();
-                }
-            } else if record.price < 0.0 {
+            fx_rate,
+            isin: field(&record, columns.isin).to_string(),
+            fingerprint,
+            ghostfolio_symbol: None,
+        };
+        // Cash only rows go both ways, e.g. negative interest or refunded tax, so the sign of the
+        // amount decides. Övrigt rows without a cash amount are split legs, see `apply_splits`
+        if matches!(
+            record.avanza_type,
+            AvanzaTransactionType::Other
+                | AvanzaTransactionType::Interest
+                | AvanzaTransactionType::InterestCompensation
+                | AvanzaTransactionType::ForeignTax
+                | AvanzaTransactionType::PreliminaryTax
+                | AvanzaTransactionType::YieldTax
+        ) {
+            if record.price < Decimal::ZERO {
                 // Probably fee
                 record.transaction_type = GhostfolioType::FEE;
-            } else if record.price > 0.0 {
+            } else if record.price > Decimal::ZERO {
                 // INTEREST
                 record.transaction_type = GhostfolioType::INTEREST;
             }
         }
-        record
+        Ok(record)
+    }
+
+    /// Exchange rate from instrument to settlement currency. Older exports lack the rate, then it
+    /// is derived from the settled amount. A rate that is not positive, e.g. when the settled
+    /// amount is all courtage, is unknown and 1 is used
+    fn exchange_rate(&self) -> Decimal {
+        if self.instrument_currency == self.settlement_currency {
+            return Decimal::ONE;
+        }
+        if let Some(rate) = self.known_fx_rate() {
+            return rate;
+        }
+        let value = (self.amount * self.price_per_unit).abs();
+        if value.is_zero() || self.price.is_zero() {
+            return Decimal::ONE;
+        }
+        let rate = match self.transaction_type {
+            GhostfolioType::BUY => (self.price.abs() - self.fee.abs()) / value,
+            GhostfolioType::SELL => (self.price.abs() + self.fee.abs()) / value,
+            _ => self.price.abs() / value,
+        };
+        if rate > Decimal::ZERO {
+            rate
+        } else {
+            Decimal::ONE
+        }
+    }
+
+    /// The exchange rate from the export, if it has one that can be used
+    fn known_fx_rate(&self) -> Option<Decimal> {
+        self.fx_rate.filter(|rate| *rate > Decimal::ZERO)
+    }
+
+    /// Avanza's currency exchange fee in the settlement currency, the part of the settled amount
+    /// not explained by the trade value and the courtage. Only known when the export includes
+    /// the exchange rate
+    fn currency_exchange_fee(&self) -> Decimal {
+        let Some(rate) = self.known_fx_rate() else {
+            return Decimal::ZERO;
+        };
+        if self.instrument_currency == self.settlement_currency {
+            return Decimal::ZERO;
+        }
+        let value = (self.amount * self.price_per_unit).abs() * rate;
+        let fee = match self.transaction_type {
+            GhostfolioType::BUY => self.price.abs() - value - self.fee.abs(),
+            GhostfolioType::SELL => value - self.fee.abs() - self.price.abs(),
+            _ => Decimal::ZERO,
+        };
+        fee.max(Decimal::ZERO)
+    }
+
+    fn has_isin(&self) -> bool {
+        !self.isin.is_empty() && self.isin != "-"
+    }
+
+    /// A holding moved in or out of the account by an internal transfer
+    fn is_transfer_leg(&self) -> bool {
+        matches!(
+            self.avanza_type,
+            AvanzaTransactionType::SecuritiesTransfer
+                | AvanzaTransactionType::TransferFrom
+                | AvanzaTransactionType::TransferTo
+        ) && self.transaction_type == GhostfolioType::OTHER
+            && !self.amount.is_zero()
+            && self.has_isin()
+    }
+
+    /// One of the two rows Avanza uses for a split, the old quantity removed and the new added
+    fn is_split_leg(&self) -> bool {
+        matches!(
+            self.avanza_type,
+            AvanzaTransactionType::Split | AvanzaTransactionType::Other
+        ) && self.transaction_type == GhostfolioType::OTHER
+            && self.price.is_zero()
+            && !self.amount.is_zero()
+            && self.has_isin()
+    }
+
+    /// The buying leg of a split or transfer, after `apply_paired_rows`
+    fn is_paired_buy(&self) -> bool {
+        self.transaction_type == GhostfolioType::BUY
+            && matches!(
+                self.avanza_type,
+                AvanzaTransactionType::Split
+                    | AvanzaTransactionType::Other
+                    | AvanzaTransactionType::SecuritiesTransfer
+                    | AvanzaTransactionType::TransferFrom
+                    | AvanzaTransactionType::TransferTo
+            )
+    }
+
+    /// Symbol used in Ghostfolio, the resolved symbol when there is one otherwise the
+    /// description
+    fn symbol(&self) -> GhostfolioSymbol {
+        self.ghostfolio_symbol
+            .clone()
+            .unwrap_or_else(|| GhostfolioSymbol {
+                symbol: self.security.clone(),
+                data_source: "MANUAL".to_string(),
+                hit: None,
+            })
+    }
+
+    /// Converts the record to a Ghostfolio activity, returns None for records that can not be
+    /// represented in Ghostfolio
+    pub fn to_activity(&self) -> Option<Activity> {
+        // Trades are in the instrument currency so the fee, paid in the settlement currency, is
+        // converted. Cash only rows are in the settlement currency
+        let (quantity, unit_price, fee, currency) = match self.transaction_type {
+            GhostfolioType::BUY | GhostfolioType::SELL | GhostfolioType::DIVIDEND => (
+                self.amount.abs(),
+                self.price_per_unit.abs(),
+                (self.fee.abs() + self.currency_exchange_fee()) / self.exchange_rate(),
+                &self.instrument_currency,
+            ),
+            GhostfolioType::FEE => (
+                Decimal::ONE,
+                Decimal::ZERO,
+                self.price.abs(),
+                &self.settlement_currency,
+            ),
+            GhostfolioType::INTEREST => (
+                Decimal::ONE,
+                self.price.abs(),
+                Decimal::ZERO,
+                &self.settlement_currency,
+            ),
+            _ => return None,
+        };
+        let symbol = self.symbol();
+        Some(Activity {
+            account_id: self.account.clone(),
+            currency: currency.clone(),
+            data_source: symbol.data_source,
+            date: ghostfolio_date(self.date),
+            fee: to_f64(fee),
+            quantity: to_f64(quantity),
+            symbol: symbol.symbol,
+            type_field: self.transaction_type.clone(),
+            unit_price: to_f64(unit_price),
+        })
+    }
+}
+
+/// Maps the Avanza account of every record to its Ghostfolio account, asking once per account.
+/// With `dry_run` no accounts are created and new mappings are not saved
+async fn map_accounts(ghostfolio: &mut GhostfolioApi, records: &mut [Record], dry_run: bool) {
+    let mut mapping: HashMap<String, String> = HashMap::new();
+    for record in records.iter_mut() {
+        if !mapping.contains_key(&record.avanza_account) {
+            let account = ghostfolio
+                .get_account_mapping(record.avanza_account.clone(), dry_run)
+                .await;
+            mapping.insert(record.avanza_account.clone(), account);
+        }
+        record.account = mapping[&record.avanza_account].clone();
+    }
+}
+
+/// Average cost per share of the holding in the account from the records before `date`.
+/// The export lists the newest rows first, so it is read backwards. Split and transfer legs of
+/// the same day are in no particular order, the old holding is sold before the new one is bought
+fn average_cost(records: &[Record], account: &str, isin: &str, date: NaiveDate) -> Decimal {
+    let mut holding: Vec<&Record> = records
+        .iter()
+        .rev()
+        .filter(|r| r.account == account && r.isin == isin && r.date < date)
+        .collect();
+    holding.sort_by_key(|record| (record.date, record.is_paired_buy()));
+    let (mut quantity, mut cost) = (Decimal::ZERO, Decimal::ZERO);
+    for record in holding {
+        match record.transaction_type {
+            GhostfolioType::BUY => {
+                quantity += record.amount.abs();
+                cost += record.amount.abs() * record.price_per_unit.abs();
+            }
+            GhostfolioType::SELL => {
+                if quantity > Decimal::ZERO {
+                    cost -= cost / quantity * record.amount.abs();
+                }
+                quantity -= record.amount.abs();
+            }
+            _ => {}
+        }
+    }
+    if quantity > Decimal::ZERO {
+        cost / quantity
+    } else {
+        Decimal::ZERO
+    }
+}
+
+/// Turns split and transfer rows into activities. They are handled day by day in chronological
+/// order since both depend on the cost basis built up by the earlier rows
+fn apply_paired_rows(records: &mut [Record]) {
+    let dates: BTreeSet<NaiveDate> = records
+        .iter()
+        .filter(|record| record.is_split_leg() || record.is_transfer_leg())
+        .map(|record| record.date)
+        .collect();
+    for date in dates {
+        apply_splits(records, date);
+        apply_transfers(records, date);
+    }
+}
+
+/// Pairs split legs on account and ISIN and turns them into a sell of the old quantity and a
+/// buy of the new quantity. The total cost is kept, so the holding and its cost basis in
+/// Ghostfolio match Avanza after the split
+fn apply_splits(records: &mut [Record], date: NaiveDate) {
+    let mut legs: BTreeMap<(String, String), Vec<usize>> = BTreeMap::new();
+    for (index, record) in records.iter().enumerate() {
+        if record.date == date && record.is_split_leg() {
+            legs.entry((record.account.clone(), record.isin.clone()))
+                .or_default()
+                .push(index);
+        }
+    }
+    for ((account, isin), indices) in legs {
+        let old = indices.iter().find(|&&i| records[i].amount < Decimal::ZERO);
+        let new = indices.iter().find(|&&i| records[i].amount > Decimal::ZERO);
+        let (Some(&old), Some(&new)) = (old, new) else {
+            println!("Unpaired split row for {} on {}", isin, date);
+            continue;
+        };
+        if indices.len() != 2 {
+            println!("Ambiguous split rows for {} on {}", isin, date);
+            continue;
+        }
+        let old_quantity = records[old].amount.abs();
+        let new_quantity = records[new].amount.abs();
+        let cost = average_cost(records, &account, &isin, date);
+        println!(
+            "Split {} on {}: {} -> {} (ratio {})",
+            records[new].security,
+            date,
+            old_quantity,
+            new_quantity,
+            new_quantity / old_quantity
+        );
+
+        records[old].transaction_type = GhostfolioType::SELL;
+        records[old].amount = old_quantity;
+        records[old].price_per_unit = cost;
+        records[old].fee = Decimal::ZERO;
+        records[new].transaction_type = GhostfolioType::BUY;
+        records[new].price_per_unit = cost * old_quantity / new_quantity;
+        records[new].fee = Decimal::ZERO;
+    }
+}
+
+/// Pairs a holding moved out of one account with the same quantity moved into another, e.g.
+/// from a KF to an ISK. The move becomes a sell in the old account and a buy in the new one,
+/// both at the original cost basis
+fn apply_transfers(records: &mut [Record], date: NaiveDate) {
+    let legs: Vec<usize> = (0..records.len())
+        .filter(|&i| records[i].date == date && records[i].is_transfer_leg())
+        .collect();
+    let outgoing: Vec<usize> = legs
+        .iter()
+        .copied()
+        .filter(|&i| records[i].amount < Decimal::ZERO)
+        .collect();
+    let mut paired: HashSet<usize> = HashSet::new();
+    for out in outgoing {
+        let Some(&into) = legs.iter().find(|&&i| {
+            !paired.contains(&i)
+                && records[i].isin == records[out].isin
+                && records[i].amount == -records[out].amount
+        }) else {
+            println!("Unpaired transfer of {} on {}", records[out].security, date);
+            continue;
+        };
+        paired.insert(into);
+        let quantity = records[out].amount.abs();
+        let cost = average_cost(records, &records[out].account, &records[out].isin, date);
+        println!(
+            "Transfer {} {} on {} at cost {}",
+            quantity, records[out].security, date, cost
+        );
+
+        records[out].transaction_type = GhostfolioType::SELL;
+        records[out].amount = quantity;
+        records[out].price_per_unit = cost;
+        records[out].fee = Decimal::ZERO;
+        records[into].transaction_type = GhostfolioType::BUY;
+        records[into].price_per_unit = cost;
+        records[into].fee = Decimal::ZERO;
+    }
+}
+
+/// Cash balance per Ghostfolio account and settlement currency, the sum of the amount of every
+/// row. Only correct when the export covers the full history of the accounts
+fn cash_balances(records: &[Record]) -> BTreeMap<(String, String), Decimal> {
+    let mut balances: BTreeMap<(String, String), Decimal> = BTreeMap::new();
+    for record in records.iter().filter(|record| !record.price.is_zero()) {
+        *balances
+            .entry((record.account.clone(), record.settlement_currency.clone()))
+            .or_default() += record.price;
+    }
+    balances
+}
+
+/// Sets the cash balance of the Ghostfolio accounts, with `dry_run` the changes are only listed.
+/// Balances in another currency than the account's are skipped
+async fn update_cash_balances(
+    ghostfolio: &GhostfolioApi,
+    balances: &BTreeMap<(String, String), Decimal>,
+    dry_run: bool,
+) {
+    for ((account_id, currency), balance) in balances {
+        let balance = to_f64(balance.round_dp(2));
+        let Some(account) = ghostfolio.get_account(account_id).await else {
+            println!("Skipping balance of unknown account {}", account_id);
+            continue;
+        };
+        if account.currency != *currency {
+            println!(
+                "Skipping balance of {:.2} {} for account {}, the account is in {}",
+                balance, currency, account.name, account.currency
+            );
+            continue;
+        }
+        if account.balance == balance {
+            continue;
+        }
+        if dry_run {
+            println!(
+                "Would update balance of account {} from {} to {}",
+                account_id, account.balance, balance
+            );
+            continue;
+        }
+        match ghostfolio.update_account_balance(&account, balance).await {
+            Ok(()) => println!("Updated balance of account {} to {}", account_id, balance),
+            Err(e) => println!("Failed to update balance of account {}: {}", account_id, e),
+        }
+    }
+}
+
+/// Identical rows can legitimately occur, e.g. two equal buys on the same day. Each repeat gets
+/// its occurrence number appended so that the fingerprints stay unique and stable between exports
+fn number_duplicate_fingerprints(records: &mut [Record]) {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for record in records.iter_mut() {
+        let count = seen.entry(record.fingerprint.clone()).or_insert(0);
+        *count += 1;
+        if *count > 1 {
+            record.fingerprint = format!("{}#{}", record.fingerprint, count);
+        }
+    }
+}
+
+/// Writes the activities as a Ghostfolio import file, `{"activities": [...]}`
+pub fn write_import_file(path: &Path, activities: Vec<Activity>) -> Result<(), Box<dyn Error>> {
+    let file = std::fs::File::create(path)?;
+    serde_json::to_writer_pretty(file, &ActivityImport { activities })?;
+    Ok(())
+}
+
+/// Creates MANUAL assets that Ghostfolio does not know about yet, e.g. Swedish funds that are
+/// not available from Yahoo. With `dry_run` the missing assets are only listed
+async fn create_missing_assets(ghostfolio: &GhostfolioApi, records: &[Record], dry_run: bool) {
+    let known: HashSet<String> = ghostfolio
+        .query_assets(&AssetQuery {
+            data_source: Some("MANUAL".to_string()),
+            ..Default::default()
+        })
+        .await
+        .into_iter()
+        .map(|asset| asset.symbol)
+        .collect();
+    let mut handled: HashSet<&String> = HashSet::new();
+    for symbol in records
+        .iter()
+        .filter_map(|record| record.ghostfolio_symbol.as_ref())
+        .filter(|symbol| symbol.data_source == "MANUAL")
+    {
+        let Some(hit) = &symbol.hit else {
+            continue;
+        };
+        if known.contains(&symbol.symbol) || !handled.insert(&symbol.symbol) {
+            continue;
+        }
+        if dry_run {
+            println!("Would create MANUAL asset {}", symbol.symbol);
+            continue;
+        }
+        let result = match crate::hit_asset_profile(hit).await {
+            Ok(profile) => ghostfolio.create_asset(&symbol.symbol, &profile).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => println!("Created MANUAL asset {}", symbol.symbol),
+            Err(e) => println!("Failed to create asset {}: {}", symbol.symbol, e),
+        }
     }
 }
 
-pub async fn parse_from_file(path: PathBuf) -> () {
-    let skip_types = vec!["Insättning", "Uttag", "Värdepappersöverföring"];
+fn is_same_activity(activity: &Activity, order: &Order) -> bool {
+    order.account_id.as_deref() == Some(activity.account_id.as_str())
+        && order.date.get(..10) == activity.date.get(..10)
+        && order.type_field == activity.type_field
+        && order.symbol_profile.symbol == activity.symbol
+}
+
+/// Prints which activities would be created, which already exist in Ghostfolio and which exist
+/// but differ in quantity or unit price
+async fn print_dry_run(ghostfolio: &GhostfolioApi, activities: &[Activity]) {
+    let mut orders: Vec<Order> = Vec::new();
+    let mut account_ids: Vec<&String> = activities.iter().map(|a| &a.account_id).collect();
+    account_ids.sort();
+    account_ids.dedup();
+    for account_id in account_ids {
+        orders.extend(ghostfolio.get_orders(account_id).await);
+    }
+
+    let mut created = Vec::new();
+    let mut existing = Vec::new();
+    let mut differing = Vec::new();
+    let same_values = |activity: &Activity, order: &Order| {
+        (order.quantity - activity.quantity).abs() <= f64::EPSILON
+            && (order.unit_price - activity.unit_price).abs() <= f64::EPSILON
+    };
+    for activity in activities {
+        // Each order can only match one activity, exact matches are taken first so that e.g.
+        // two buys of the same symbol on one day pair up with their own orders
+        let position = orders
+            .iter()
+            .position(|order| is_same_activity(activity, order) && same_values(activity, order))
+            .or_else(|| {
+                orders
+                    .iter()
+                    .position(|order| is_same_activity(activity, order))
+            });
+        match position.map(|position| orders.remove(position)) {
+            None => created.push(activity),
+            Some(order) if !same_values(activity, &order) => differing.push((activity, order)),
+            Some(_) => existing.push(activity),
+        }
+    }
+
+    let format_activity = |a: &Activity| {
+        format!(
+            "{} {:?} {} {} x {} {}",
+            &a.date[..10],
+            a.type_field,
+            a.symbol,
+            a.quantity,
+            a.unit_price,
+            a.currency
+        )
+    };
+    println!("Would create {} activities:", created.len());
+    for activity in created {
+        println!("  + {}", format_activity(activity));
+    }
+    println!("Already existing {} activities:", existing.len());
+    for activity in existing {
+        println!("  = {}", format_activity(activity));
+    }
+    println!("Differing {} activities:", differing.len());
+    for (activity, order) in differing {
+        println!(
+            "  ~ {} (Ghostfolio: {} x {})",
+            format_activity(activity),
+            order.quantity,
+            order.unit_price
+        );
+    }
+}
+
+/// Lists the records that could not be turned into Ghostfolio activities, grouped by type
+fn print_unhandled(records: &[Record]) {
+    let unhandled: Vec<&Record> = records
+        .iter()
+        .filter(|record| record.to_activity().is_none())
+        .collect();
+    if unhandled.is_empty() {
+        return;
+    }
+    let mut by_type: BTreeMap<String, Vec<&Record>> = BTreeMap::new();
+    for record in unhandled.iter() {
+        let avanza_type = match &record.avanza_type {
+            AvanzaTransactionType::Unknown(s) => format!("Unknown type {}", s),
+            avanza_type => format!("{:?}", avanza_type),
+        };
+        by_type.entry(avanza_type).or_default().push(record);
+    }
+    println!("{} records were not imported:", unhandled.len());
+    for (avanza_type, records) in by_type {
+        println!("  {} ({}):", avanza_type, records.len());
+        for record in records {
+            println!(
+                "    {} {} {} {}",
+                record.date, record.security, record.amount, record.price
+            );
+        }
+    }
+}
+
+/// With `sync_balances` the cash balance of the accounts is set from the export, which has to
+/// cover the full account history for that to be correct
+pub async fn parse_from_file(
+    path: PathBuf,
+    output: Option<PathBuf>,
+    dry_run: bool,
+    sync_balances: bool,
+) {
     let mut rdr = csv::ReaderBuilder::new()
         .delimiter(b';')
         .from_path(path)
         .expect("Failed to read csv file");
+    let columns = match ColumnLayout::from_headers(rdr.headers().expect("Failed to read headers")) {
+        Ok(columns) => columns,
+        Err(e) => {
+            println!("Unsupported export format: {}", e);
+            return;
+        }
+    };
     let mut parsed = Vec::new();
+    let mut errors: Vec<String> = Vec::new();
     for result in rdr.records() {
-        if let Ok(record) = result {
-            if skip_types.contains(&record.get(2).unwrap()) {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                errors.push(e.to_string());
                 continue;
             }
-            let parsed_record = Record::from_csv_record(record).await;
-            parsed.push(parsed_record);
+        };
+        match Record::from_csv_record(record, &columns) {
+            Ok(parsed_record) => parsed.push(parsed_record),
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    if !errors.is_empty() {
+        println!("Failed to parse {} rows, nothing imported:", errors.len());
+        for error in errors {
+            println!("  {}", error);
+        }
+        return;
+    }
+    let mut ghostfolio = GhostfolioApi::new();
+    map_accounts(&mut ghostfolio, &mut parsed, dry_run).await;
+    number_duplicate_fingerprints(&mut parsed);
+    apply_paired_rows(&mut parsed);
+    let balances = cash_balances(&parsed);
+
+    let mut ledger = Ledger::new();
+    let (already_imported, mut parsed): (Vec<Record>, Vec<Record>) = parsed
+        .into_iter()
+        .partition(|record| ledger.contains(&record.fingerprint));
+    if !already_imported.is_empty() {
+        println!(
+            "Skipping {} already imported records:",
+            already_imported.len()
+        );
+        for record in &already_imported {
+            println!(
+                "  {} {:?} {} {}",
+                record.date, record.transaction_type, record.security, record.amount
+            );
+        }
+    }
+
+    let mut resolver = SymbolResolver::new();
+    for record in parsed.iter_mut().filter(|record| record.has_isin()) {
+        match resolver
+            .resolve(ghostfolio.full_config_mut(), &record.isin)
+            .await
+        {
+            Ok(symbol) => record.ghostfolio_symbol = Some(symbol),
+            Err(e) => {
+                println!("{}, nothing imported", e);
+                // Keep the mappings answered so far
+                if !dry_run {
+                    ghostfolio.save_config();
+                }
+                return;
+            }
         }
     }
-    println!("{:?}", parsed[1]);
+    // Symbol mappings added above are stored once for the whole file
+    if !dry_run {
+        ghostfolio.save_config();
+    }
+
+    let activities: Vec<Activity> = parsed.iter().filter_map(Record::to_activity).collect();
+    let skipped = parsed.len() - activities.len();
+    print_unhandled(&parsed);
+    println!(
+        "Parsed {} records, importing {} activities ({} skipped)",
+        parsed.len(),
+        activities.len(),
+        skipped
+    );
+    if dry_run {
+        create_missing_assets(&ghostfolio, &parsed, true).await;
+        print_dry_run(&ghostfolio, &activities).await;
+        if sync_balances {
+            update_cash_balances(&ghostfolio, &balances, true).await;
+        }
+        return;
+    }
+    if let Some(output) = output {
+        match write_import_file(&output, activities) {
+            Ok(()) => {
+                println!("Wrote import file to {:?}", output);
+                for ((account_id, currency), balance) in &balances {
+                    println!(
+                        "Cash balance of account {}: {:.2} {}",
+                        account_id, balance, currency
+                    );
+                }
+            }
+            Err(e) => println!("Failed to write import file: {}", e),
+        }
+        return;
+    }
+    create_missing_assets(&ghostfolio, &parsed, false).await;
+    match ghostfolio.import_activities(activities).await {
+        Ok(()) => {
+            parsed
+                .iter()
+                .filter(|record| record.to_activity().is_some())
+                .for_each(|record| ledger.insert(record.fingerprint.clone()));
+            ledger.save();
+            println!("Import successful");
+            if sync_balances {
+                update_cash_balances(&ghostfolio, &balances, false).await;
+            }
+        }
+        Err(e) => println!("Import failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const OLD_HEADERS: &str = "Datum;Konto;Typ av transaktion;Värdepapper/beskrivning;Antal;Kurs;Belopp;Courtage;Valuta;ISIN;Resultat";
+    const NEW_HEADERS: &str = "Datum;Konto;Typ av transaktion;Värdepapper/beskrivning;Antal;Kurs;Belopp;Transaktionsvaluta;Courtage (SEK);Växlingskurs;Instrumentvaluta;ISIN;Resultat";
+
+    fn decimal(s: &str) -> Decimal {
+        Decimal::from_str(s).unwrap()
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::from_str(s).unwrap()
+    }
+
+    /// Parses an export, newest row first like Avanza, with every account mapped to itself
+    fn parse(headers: &str, rows: &[&str]) -> Vec<Record> {
+        let export = format!("{}\n{}", headers, rows.join("\n"));
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(export.as_bytes());
+        let columns = ColumnLayout::from_headers(reader.headers().unwrap()).unwrap();
+        reader
+            .records()
+            .map(|row| {
+                let mut record = Record::from_csv_record(row.unwrap(), &columns).unwrap();
+                record.account = record.avanza_account.clone();
+                record
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    const SPLIT_OLD_LEG: &str = "2024-02-01;ISK;Split;Bolaget;-10;-;-;-;SEK;SE0000000001;-";
+    const SPLIT_NEW_LEG: &str = "2024-02-01;ISK;Split;Bolaget;20;-;-;-;SEK;SE0000000001;-";
+    const SPLIT_BUY: &str = "2024-01-02;ISK;Köp;Bolaget;10;100;-1 000;0;SEK;SE0000000001;-";
+
+    fn assert_split_keeps_cost(rows: &[&str]) {
+        let mut records = parse(OLD_HEADERS, rows);
+        apply_paired_rows(&mut records);
+
+        let old = records
+            .iter()
+            .find(|r| r.amount == decimal("10") && r.avanza_type == AvanzaTransactionType::Split)
+            .unwrap();
+        assert_eq!(old.transaction_type, GhostfolioType::SELL);
+        assert_eq!(old.price_per_unit, decimal("100"));
+        let new = records.iter().find(|r| r.amount == decimal("20")).unwrap();
+        assert_eq!(new.transaction_type, GhostfolioType::BUY);
+        assert_eq!(new.price_per_unit, decimal("50"));
+        assert_eq!(
+            average_cost(&records, "ISK", "SE0000000001", date("2024-03-01")),
+            decimal("50")
+        );
+    }
+
+    #[test]
+    fn split_keeps_cost_with_old_leg_first() {
+        assert_split_keeps_cost(&[SPLIT_OLD_LEG, SPLIT_NEW_LEG, SPLIT_BUY]);
+    }
+
+    #[test]
+    fn split_keeps_cost_with_new_leg_first() {
+        assert_split_keeps_cost(&[SPLIT_NEW_LEG, SPLIT_OLD_LEG, SPLIT_BUY]);
+    }
+
+    #[test]
+    fn transfer_moves_holding_at_cost() {
+        let mut records = parse(
+            OLD_HEADERS,
+            &[
+                "2024-02-01;ISK;Värdepappersöverföring;Bolaget;10;-;-;-;SEK;SE0000000001;-",
+                "2024-02-01;KF;Värdepappersöverföring;Bolaget;-10;-;-;-;SEK;SE0000000001;-",
+                "2024-01-03;KF;Köp;Bolaget;5;130;-650;0;SEK;SE0000000001;-",
+                "2024-01-02;KF;Köp;Bolaget;5;110;-550;0;SEK;SE0000000001;-",
+            ],
+        );
+        apply_paired_rows(&mut records);
+
+        assert_eq!(records[1].transaction_type, GhostfolioType::SELL);
+        assert_eq!(records[1].amount, decimal("10"));
+        assert_eq!(records[1].price_per_unit, decimal("120"));
+        assert_eq!(records[0].transaction_type, GhostfolioType::BUY);
+        assert_eq!(records[0].price_per_unit, decimal("120"));
+        assert_eq!(
+            average_cost(&records, "ISK", "SE0000000001", date("2024-03-01")),
+            decimal("120")
+        );
+        assert_eq!(
+            average_cost(&records, "KF", "SE0000000001", date("2024-03-01")),
+            Decimal::ZERO
+        );
+    }
+
+    #[test]
+    fn reads_old_layout() {
+        let records = parse(
+            OLD_HEADERS,
+            &["2024-01-02;ISK;Köp;Bolaget;10;100;-1 001;1;SEK;SE0000000001;-"],
+        );
+        let record = &records[0];
+        assert_eq!(record.date, date("2024-01-02"));
+        assert_eq!(record.transaction_type, GhostfolioType::BUY);
+        assert_eq!(record.instrument_currency, "SEK");
+        assert_eq!(record.settlement_currency, "SEK");
+        assert_eq!(record.fx_rate, None);
+        assert_eq!(record.price, decimal("-1001"));
+    }
+
+    #[test]
+    fn reads_new_layout() {
+        let records = parse(
+            NEW_HEADERS,
+            &["2024-03-01;ISK;Köp;Apple;10;100;-10 025;SEK;10;10;USD;US0378331005;-"],
+        );
+        let record = &records[0];
+        assert_eq!(record.instrument_currency, "USD");
+        assert_eq!(record.settlement_currency, "SEK");
+        assert_eq!(record.fx_rate, Some(decimal("10")));
+        assert_eq!(record.fee, decimal("10"));
+    }
+
+    #[test]
+    fn missing_columns_are_reported() {
+        let headers = csv::StringRecord::from(vec![
+            "Datum",
+            "Konto",
+            "Typ av transaktion",
+            "Värdepapper/beskrivning",
+            "Antal",
+            "Kurs",
+            "Belopp",
+            "Valuta",
+        ]);
+        let error = ColumnLayout::from_headers(&headers).err().unwrap();
+        assert!(error
+            .to_string()
+            .contains("Courtage or Courtage (SEK), ISIN"));
+    }
+
+    #[test]
+    fn usd_buy_with_exchange_rate_includes_exchange_fee() {
+        let records = parse(
+            NEW_HEADERS,
+            &["2024-03-01;ISK;Köp;Apple;10;100;-10 025;SEK;10;10;USD;US0378331005;-"],
+        );
+        let record = &records[0];
+        assert_eq!(record.exchange_rate(), decimal("10"));
+        assert_eq!(record.currency_exchange_fee(), decimal("15"));
+
+        let activity = record.to_activity().unwrap();
+        assert_eq!(activity.currency, "USD");
+        assert_eq!(activity.unit_price, 100.0);
+        assert_eq!(activity.quantity, 10.0);
+        assert_close(activity.fee, 2.5);
+    }
+
+    #[test]
+    fn usd_buy_without_exchange_rate_derives_it() {
+        let records = parse(
+            NEW_HEADERS,
+            &["2024-03-01;ISK;Köp;Apple;10;100;-10 025;SEK;10;;USD;US0378331005;-"],
+        );
+        let record = &records[0];
+        assert_eq!(record.exchange_rate(), decimal("10.015"));
+        assert_eq!(record.currency_exchange_fee(), Decimal::ZERO);
+
+        let activity = record.to_activity().unwrap();
+        assert_eq!(activity.currency, "USD");
+        assert_close(activity.fee, 10.0 / 10.015);
+    }
+
+    #[test]
+    fn cash_rows_are_typed_by_sign() {
+        let records = parse(
+            OLD_HEADERS,
+            &[
+                "2024-01-31;ISK;Ränta;Ränta;-;-;-3;-;SEK;-;-",
+                "2024-01-31;ISK;Ränta;Ränta;-;-;5;-;SEK;-;-",
+                "2024-01-15;ISK;Utländsk källskatt;Apple;-;-;-12;-;SEK;-;-",
+                "2024-01-16;ISK;Utländsk källskatt;Apple;-;-;12;-;SEK;-;-",
+                "2024-01-10;ISK;Avkastningsskatt;Avkastningsskatt;-;-;-40;-;SEK;-;-",
+            ],
+        );
+        let types: Vec<GhostfolioType> = records
+            .iter()
+            .map(|record| record.transaction_type.clone())
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                GhostfolioType::FEE,
+                GhostfolioType::INTEREST,
+                GhostfolioType::FEE,
+                GhostfolioType::INTEREST,
+                GhostfolioType::FEE,
+            ]
+        );
+        let negative_interest = records[0].to_activity().unwrap();
+        assert_eq!(negative_interest.fee, 3.0);
+        let refund = records[3].to_activity().unwrap();
+        assert_eq!(refund.unit_price, 12.0);
+    }
+
+    #[test]
+    fn import_file_matches_expected_output() {
+        let records = parse(
+            OLD_HEADERS,
+            &[
+                "2024-01-03;ISK;Utdelning;Bolaget;10;2;20;-;SEK;SE0000000001;-",
+                "2024-01-02;ISK;Köp;Bolaget;10;100;-1 001;1;SEK;SE0000000001;-",
+            ],
+        );
+        let activities: Vec<Activity> = records.iter().filter_map(Record::to_activity).collect();
+        let import = serde_json::to_value(ActivityImport { activities }).unwrap();
+        let expected = serde_json::json!({
+            "activities": [
+                {
+                    "accountId": "ISK",
+                    "currency": "SEK",
+                    "dataSource": "MANUAL",
+                    "date": "2024-01-03T00:00:00.000Z",
+                    "fee": 0.0,
+                    "quantity": 10.0,
+                    "symbol": "Bolaget",
+                    "type": "DIVIDEND",
+                    "unitPrice": 2.0
+                },
+                {
+                    "accountId": "ISK",
+                    "currency": "SEK",
+                    "dataSource": "MANUAL",
+                    "date": "2024-01-02T00:00:00.000Z",
+                    "fee": 1.0,
+                    "quantity": 10.0,
+                    "symbol": "Bolaget",
+                    "type": "BUY",
+                    "unitPrice": 100.0
+                }
+            ]
+        });
+        assert_eq!(import, expected);
+        let round_trip: ActivityImport = serde_json::from_value(import).unwrap();
+        assert_eq!(round_trip.activities.len(), 2);
+    }
 }
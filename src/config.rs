@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 
 use crate::ghostfolio::GhostfolioConfig;
 use serde::{Deserialize, Serialize};
@@ -7,20 +8,33 @@ use serde::{Deserialize, Serialize};
 pub struct Config {
     pub ghostfolio: Option<GhostfolioConfig>,
     pub avanza_to_ghostfolio_ticker: HashMap<String, String>,
+    #[serde(default)]
+    pub avanza_to_ghostfolio_data_source: HashMap<String, String>,
+    /// ISIN -> Ghostfolio symbol, for instruments that Avanza's search does not find
+    #[serde(default)]
+    pub isin_to_ghostfolio_symbol: HashMap<String, String>,
+    #[serde(default)]
+    pub isin_to_ghostfolio_data_source: HashMap<String, String>,
 }
 
-pub const DEFAULT_CONFIG_PATH: &str = "~/.avanza-ghostfolio-cli/config.json";
 const CONFIG_DIR_NAME: &str = ".avanza-ghostfolio-cli";
+
+/// Directory holding `config.json` and other state, created if missing
+pub fn config_dir() -> PathBuf {
+    let config_dir = dirs::home_dir()
+        .expect("Failed to get home dir")
+        .join(CONFIG_DIR_NAME);
+    if !config_dir.exists() {
+        std::fs::create_dir_all(&config_dir).expect("Failed to create config dir");
+    }
+    config_dir
+}
+
 impl Config {
     pub fn new() -> Config {
-        let home_dir = dirs::home_dir().expect("Failed to get home dir");
-        if !home_dir.join(CONFIG_DIR_NAME).exists() {
-            std::fs::create_dir_all(home_dir.join(CONFIG_DIR_NAME))
-                .expect("Failed to create config dir");
-        }
-        let config_path = home_dir.join(format!("{}/config.json", CONFIG_DIR_NAME));
+        let config_path = config_dir().join("config.json");
         println!("Config path: {:?}", config_path);
-        let config = if config_path.exists() {
+        if config_path.exists() {
             let config_file = std::fs::File::open(config_path).expect("Failed to open config file");
 
             serde_json::from_reader(config_file).expect("Failed to parse config file")
@@ -28,19 +42,33 @@ impl Config {
             let config = Config {
                 ghostfolio: None,
                 avanza_to_ghostfolio_ticker: HashMap::new(),
+                avanza_to_ghostfolio_data_source: HashMap::new(),
+                isin_to_ghostfolio_symbol: HashMap::new(),
+                isin_to_ghostfolio_data_source: HashMap::new(),
             };
             let config_file =
                 std::fs::File::create(config_path).expect("Failed to create config file");
 
             serde_json::to_writer(config_file, &config).expect("Failed to write config file");
             config
-        };
-        config
+        }
+    }
+    /// Avanza orderbook id mapped to a Ghostfolio symbol, data source defaults to MANUAL
+    pub fn orderbook_id(&self, data_source: &str, symbol: &str) -> Option<String> {
+        self.avanza_to_ghostfolio_ticker
+            .iter()
+            .find(|(orderbook_id, ghostfolio_symbol)| {
+                *ghostfolio_symbol == symbol
+                    && self
+                        .avanza_to_ghostfolio_data_source
+                        .get(*orderbook_id)
+                        .map_or("MANUAL", |x| x.as_str())
+                        == data_source
+            })
+            .map(|(orderbook_id, _)| orderbook_id.clone())
     }
     pub fn save(&self) {
-        let config_path = dirs::home_dir()
-            .expect("Failed to get home dir")
-            .join(".avanza-ghostfolio-cli/config.json");
+        let config_path = config_dir().join("config.json");
         let config_file = std::fs::File::create(config_path).expect("Failed to create config file");
         serde_json::to_writer(config_file, &self).expect("Failed to write config file");
     }
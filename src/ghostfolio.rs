@@ -1,10 +1,14 @@
+use crate::avanza::transaction_history_parser::GhostfolioType;
 use crate::config::Config;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
 use inquire::Select;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::string::ToString;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::Mutex;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -31,19 +35,106 @@ pub struct MarketData {
     pub activities_count: i64,
 }
 
+const ASSET_PAGE_SIZE: usize = 100;
+
+/// Filters and sort order supported by the admin market-data endpoint, None fields are not sent
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct AssetQuery {
+    /// E.g. STOCK, MUTUALFUND or ETF
+    pub asset_sub_classes: Vec<String>,
+    pub data_source: Option<String>,
+    /// Searches symbol, name and ISIN
+    pub query: Option<String>,
+    /// E.g. symbol, marketDataItemCount or sectorsCount
+    pub sort_column: Option<String>,
+    /// asc or desc
+    pub sort_direction: Option<String>,
+}
+
+impl AssetQuery {
+    fn params(&self) -> Vec<(&'static str, String)> {
+        let mut params = Vec::new();
+        if !self.asset_sub_classes.is_empty() {
+            params.push(("assetSubClasses", self.asset_sub_classes.join(",")));
+        }
+        let optional = [
+            ("dataSource", &self.data_source),
+            ("query", &self.query),
+            ("sortColumn", &self.sort_column),
+            ("sortDirection", &self.sort_direction),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                params.push((name, value.clone()));
+            }
+        }
+        params
+    }
+}
+
+/// Asset profile fields that can be set through the admin profile-data endpoint
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetProfile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asset_class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asset_sub_class: Option<String>,
+    /// In the format [{code: "SE", weight: 0.5}, ...]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub countries: Option<Vec<Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// Set by Ghostfolio, only read
+    #[serde(skip_serializing)]
+    pub isin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scraper_configuration: Option<Value>,
+    /// In the format [{name: "Technology", weight: 0.5}, ...]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sectors: Option<Vec<Value>>,
+}
+
+/// Response of the admin market-data endpoint for a single asset
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketDataDetails {
+    pub asset_profile: AssetProfile,
+    #[serde(default)]
+    pub market_data: Vec<MarketPrice>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketPrice {
+    /// YYYY-MM-DD when sent, Ghostfolio returns a full timestamp
+    pub date: String,
+    pub market_price: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MarketDataUpdate<'a> {
+    market_data: &'a [MarketPrice],
+}
+
+const MARKET_DATA_BATCH_SIZE: usize = 500;
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AccountResponse {
     pub accounts: Vec<Account>,
     pub transaction_count: i64,
-    pub total_balance_in_base_currency: i64,
+    pub total_balance_in_base_currency: f64,
     pub total_value_in_base_currency: f64,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Account {
-    pub balance: i64,
+    pub balance: f64,
     pub comment: Value,
     pub created_at: String,
     pub currency: String,
@@ -57,10 +148,62 @@ pub struct Account {
     pub platform: Value,
     pub transaction_count: i64,
     pub value_in_base_currency: f64,
-    pub balance_in_base_currency: i64,
+    pub balance_in_base_currency: f64,
     pub value: f64,
 }
 
+/// A single activity in the format accepted by Ghostfolio's `/api/v1/import` endpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Activity {
+    pub account_id: String,
+    pub currency: String,
+    pub data_source: String,
+    pub date: String,
+    pub fee: f64,
+    pub quantity: f64,
+    pub symbol: String,
+    #[serde(rename = "type")]
+    pub type_field: GhostfolioType,
+    pub unit_price: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActivityImport {
+    pub activities: Vec<Activity>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderResponse {
+    pub activities: Vec<Order>,
+    pub count: i64,
+}
+
+/// An activity as returned by Ghostfolio's `/api/v1/order` endpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Order {
+    pub id: String,
+    pub account_id: Option<String>,
+    pub date: String,
+    pub fee: f64,
+    pub quantity: f64,
+    #[serde(rename = "type")]
+    pub type_field: GhostfolioType,
+    pub unit_price: f64,
+    #[serde(rename = "SymbolProfile")]
+    pub symbol_profile: SymbolProfile,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolProfile {
+    pub currency: Option<String>,
+    pub data_source: String,
+    pub symbol: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct GhostfolioConfig {
     token: String,
@@ -89,10 +232,38 @@ impl GhostfolioConfig {
         }
     }
 }
+/// JWT received in exchange for the security token
+#[derive(Debug, Clone)]
+struct AuthToken {
+    token: String,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl AuthToken {
+    fn new(token: String) -> AuthToken {
+        let expires_at = jwt_expiry(&token);
+        AuthToken { token, expires_at }
+    }
+
+    /// Tokens are renewed a minute before they expire so that they do not run out mid-request
+    fn is_valid(&self) -> bool {
+        self.expires_at
+            .is_none_or(|expires_at| Utc::now() + Duration::seconds(60) < expires_at)
+    }
+}
+
+/// Reads the `exp` claim, tokens without one are used until Ghostfolio rejects them
+fn jwt_expiry(token: &str) -> Option<DateTime<Utc>> {
+    let payload = token.split('.').nth(1)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    let claims: Value = serde_json::from_slice(&payload).ok()?;
+    DateTime::from_timestamp(claims["exp"].as_i64()?, 0)
+}
+
 pub struct GhostfolioApi {
     client: reqwest::Client,
-    config: GhostfolioConfig,
     full_config: Config,
+    auth_token: Mutex<Option<AuthToken>>,
 }
 
 impl GhostfolioApi {
@@ -104,24 +275,182 @@ impl GhostfolioApi {
         }
         GhostfolioApi {
             client: reqwest::Client::new(),
-            config: config.ghostfolio.unwrap(),
             full_config: config,
+            auth_token: Mutex::new(None),
         }
     }
 
-    pub(crate) async fn get_assets(&self) -> Vec<MarketData> {
-        let url = format!("{}/admin/market-data?take=50", self.config.base_url);
-        let assets: GhostfolioAssets = self
+    /// Exchanges the security token from the config for a JWT and caches it
+    async fn authenticate(&self) -> Result<String, Box<dyn Error>> {
+        let url = format!("{}/api/v1/auth/anonymous", self.config().base_url);
+        let response = self
             .client
-            .get(url)
+            .post(url)
+            .json(&json!({ "accessToken": self.config().token }))
             .send()
-            .await
-            .expect("Failed to get assets")
-            .json()
-            .await
-            .expect("Failed to parse assets");
-        assets.market_data
+            .await?;
+        if !response.status().is_success() {
+            return Err(format!("Failed to authenticate: {}", response.text().await?).into());
+        }
+        let body: Value = response.json().await?;
+        let token = body["authToken"]
+            .as_str()
+            .ok_or("Authentication response has no authToken")?
+            .to_string();
+        *self.auth_token.lock().unwrap() = Some(AuthToken::new(token.clone()));
+        Ok(token)
+    }
+
+    async fn bearer_token(&self) -> Result<String, Box<dyn Error>> {
+        let cached = self.auth_token.lock().unwrap().clone();
+        match cached {
+            Some(auth_token) if auth_token.is_valid() => Ok(auth_token.token),
+            _ => self.authenticate().await,
+        }
+    }
+
+    /// Sends the request built by `request` with the bearer token. A rejected token is renewed
+    /// and the request is sent once more
+    async fn send<F>(&self, request: F) -> Result<reqwest::Response, Box<dyn Error>>
+    where
+        F: Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+    {
+        let token = self.bearer_token().await?;
+        let response = request(&self.client).bearer_auth(token).send().await?;
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+        let token = self.authenticate().await?;
+        Ok(request(&self.client).bearer_auth(token).send().await?)
+    }
+
+    pub fn full_config(&self) -> &Config {
+        &self.full_config
+    }
+
+    pub fn full_config_mut(&mut self) -> &mut Config {
+        &mut self.full_config
+    }
+
+    pub fn save_config(&self) {
+        self.full_config.save();
+    }
+
+    fn config(&self) -> &GhostfolioConfig {
+        self.full_config
+            .ghostfolio
+            .as_ref()
+            .expect("Ghostfolio config missing")
+    }
+
+    fn config_mut(&mut self) -> &mut GhostfolioConfig {
+        self.full_config
+            .ghostfolio
+            .as_mut()
+            .expect("Ghostfolio config missing")
     }
+
+    pub(crate) async fn get_assets(&self) -> Vec<MarketData> {
+        self.query_assets(&AssetQuery::default()).await
+    }
+
+    /// Lists all assets matching the query, fetching page by page until `count` is reached
+    pub(crate) async fn query_assets(&self, query: &AssetQuery) -> Vec<MarketData> {
+        let url = format!("{}/api/v1/admin/market-data", self.config().base_url);
+        let mut market_data: Vec<MarketData> = Vec::new();
+        loop {
+            let mut params = query.params();
+            params.push(("skip", market_data.len().to_string()));
+            params.push(("take", ASSET_PAGE_SIZE.to_string()));
+            let page: GhostfolioAssets = self
+                .send(|client| client.get(&url).query(&params))
+                .await
+                .expect("Failed to get assets")
+                .json()
+                .await
+                .expect("Failed to parse assets");
+            let received = page.market_data.len();
+            market_data.extend(page.market_data);
+            if received == 0 || market_data.len() as i64 >= page.count {
+                return market_data;
+            }
+        }
+    }
+    pub async fn get_asset_profile(
+        &self,
+        data_source: &str,
+        symbol: &str,
+    ) -> Result<AssetProfile, Box<dyn Error>> {
+        Ok(self
+            .get_market_data_details(data_source, symbol)
+            .await?
+            .asset_profile)
+    }
+
+    async fn get_market_data_details(
+        &self,
+        data_source: &str,
+        symbol: &str,
+    ) -> Result<MarketDataDetails, Box<dyn Error>> {
+        let url = format!(
+            "{}/api/v1/admin/market-data/{}/{}",
+            self.config().base_url,
+            data_source,
+            symbol
+        );
+        let response = self.send(|client| client.get(&url)).await?;
+        if !response.status().is_success() {
+            return Err(format!("Failed to get market data: {}", response.text().await?).into());
+        }
+        Ok(response.json().await?)
+    }
+
+    /// Stores market prices for an asset in batches, prices for existing dates are replaced.
+    /// Returns the number of prices stored
+    pub async fn update_market_data(
+        &self,
+        data_source: &str,
+        symbol: &str,
+        market_data: &[MarketPrice],
+    ) -> Result<usize, Box<dyn Error>> {
+        let url = format!(
+            "{}/api/v1/admin/market-data/{}/{}",
+            self.config().base_url,
+            data_source,
+            symbol
+        );
+        let mut stored = 0;
+        for batch in market_data.chunks(MARKET_DATA_BATCH_SIZE) {
+            let body = MarketDataUpdate { market_data: batch };
+            let response = self.send(|client| client.post(&url).json(&body)).await?;
+            if !response.status().is_success() {
+                return Err(format!(
+                    "Failed to store market data after {} prices: {}",
+                    stored,
+                    response.text().await?
+                )
+                .into());
+            }
+            stored += batch.len();
+        }
+        Ok(stored)
+    }
+
+    /// Dates, as YYYY-MM-DD, that Ghostfolio already has a price for
+    pub async fn get_market_data_dates(
+        &self,
+        data_source: &str,
+        symbol: &str,
+    ) -> Result<HashSet<String>, Box<dyn Error>> {
+        Ok(self
+            .get_market_data_details(data_source, symbol)
+            .await?
+            .market_data
+            .into_iter()
+            .filter_map(|price| price.date.get(..10).map(|date| date.to_string()))
+            .collect())
+    }
+
     pub async fn select_asset(&self) -> MarketData {
         let assets = self.get_assets().await;
 
@@ -133,26 +462,86 @@ impl GhostfolioApi {
         assets[index].clone()
     }
 
-    pub async fn create_asset(&self, asset: MarketData) {
+    /// Creates a MANUAL asset profile and fills it in with the given profile
+    pub async fn create_asset(
+        &self,
+        symbol: &str,
+        profile: &AssetProfile,
+    ) -> Result<(), Box<dyn Error>> {
         let url = format!(
             "{}/api/v1/admin/profile-data/MANUAL/{}",
-            self.config.base_url, asset.symbol
+            self.config().base_url,
+            symbol
         );
-        self.client
-            .post(url)
-            .send()
+        let response = self.send(|client| client.post(&url).json(profile)).await?;
+        if !response.status().is_success() {
+            return Err(format!("Failed to create asset: {}", response.text().await?).into());
+        }
+        // Ghostfolio creates an empty profile, the fields are set with a following patch
+        self.patch_profile("MANUAL", symbol, profile).await
+    }
+
+    /// Updates the asset profile of an existing asset, fields left as None are not changed
+    pub async fn update_assets(
+        &self,
+        asset: &MarketData,
+        profile: &AssetProfile,
+    ) -> Result<(), Box<dyn Error>> {
+        self.patch_profile(&asset.data_source, &asset.symbol, profile)
+            .await
+    }
+
+    async fn patch_profile(
+        &self,
+        data_source: &str,
+        symbol: &str,
+        profile: &AssetProfile,
+    ) -> Result<(), Box<dyn Error>> {
+        let url = format!(
+            "{}/api/v1/admin/profile-data/{}/{}",
+            self.config().base_url,
+            data_source,
+            symbol
+        );
+        let response = self.send(|client| client.patch(&url).json(profile)).await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Failed to update asset: {}", response.text().await?).into())
+        }
+    }
+
+    pub async fn get_orders(&self, account_id: &str) -> Vec<Order> {
+        let url = format!(
+            "{}/api/v1/order?accounts={}",
+            self.config().base_url,
+            account_id
+        );
+        let orders: OrderResponse = self
+            .send(|client| client.get(&url))
+            .await
+            .expect("Failed to get orders")
+            .json()
             .await
-            .expect("Failed to create asset");
+            .expect("Failed to parse orders");
+        orders.activities
     }
 
-    pub async fn update_assets(&self, asset: MarketData) {}
+    pub async fn import_activities(&self, activities: Vec<Activity>) -> Result<(), Box<dyn Error>> {
+        let url = format!("{}/api/v1/import", self.config().base_url);
+        let body = ActivityImport { activities };
+        let response = self.send(|client| client.post(&url).json(&body)).await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Failed to import activities: {}", response.text().await?).into())
+        }
+    }
 
     async fn get_accounts(&self) -> AccountResponse {
-        let url = format!("{}/api/v1/account", self.config.base_url);
+        let url = format!("{}/api/v1/account", self.config().base_url);
         let accounts: AccountResponse = self
-            .client
-            .get(url)
-            .send()
+            .send(|client| client.get(&url))
             .await
             .expect("Failed to get accounts")
             .json()
@@ -160,30 +549,131 @@ impl GhostfolioApi {
             .expect("Failed to parse accounts");
         accounts
     }
-    async fn select_account(&self) -> String {
+    pub async fn get_account(&self, account_id: &str) -> Option<Account> {
+        self.get_accounts()
+            .await
+            .accounts
+            .into_iter()
+            .find(|account| account.id == account_id)
+    }
+
+    /// Sets the balance of an account fetched by `get_account`, its other fields are kept
+    pub async fn update_account_balance(
+        &self,
+        account: &Account,
+        balance: f64,
+    ) -> Result<(), Box<dyn Error>> {
+        let url = format!("{}/api/v1/account/{}", self.config().base_url, account.id);
+        let body = json!({
+            "balance": balance,
+            "comment": account.comment,
+            "currency": account.currency,
+            "id": account.id,
+            "isExcluded": account.is_excluded,
+            "name": account.name,
+            "platformId": account.platform_id,
+        });
+        let response = self.send(|client| client.put(&url).json(&body)).await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Failed to update account: {}", response.text().await?).into())
+        }
+    }
+
+    async fn create_account(&self, name: String, currency: String) -> String {
+        let url = format!("{}/api/v1/account", self.config().base_url);
+        let body = json!({
+            "balance": 0,
+            "comment": null,
+            "currency": currency,
+            "isExcluded": false,
+            "name": name,
+            "platformId": null,
+        });
+        let account: Value = self
+            .send(|client| client.post(&url).json(&body))
+            .await
+            .expect("Failed to create account")
+            .json()
+            .await
+            .expect("Failed to parse account");
+        account["id"]
+            .as_str()
+            .expect("Created account has no id")
+            .to_string()
+    }
+
+    /// Lets the user pick one of the Ghostfolio accounts or, unless `dry_run`, create a new one.
+    /// Returns its id
+    async fn select_account(&self, avanza_account: &str, dry_run: bool) -> String {
+        const CREATE_ACCOUNT: &str = "Create new account";
         let accounts = self.get_accounts().await;
-        let options: Vec<String> = accounts
+        let mut options: Vec<String> = accounts
             .accounts
             .iter()
             .map(|account| account.name.clone())
             .collect();
-        let ans: String = Select::new("Select your account", options.clone())
-            .prompt()
-            .expect("Failed to get input");
+        if dry_run && options.is_empty() {
+            println!(
+                "No Ghostfolio accounts, would create one for {}",
+                avanza_account
+            );
+            return avanza_account.to_string();
+        }
+        if !dry_run {
+            options.push(CREATE_ACCOUNT.to_string());
+        }
+        let ans: String = Select::new(
+            &format!(
+                "Select Ghostfolio account for Avanza account {}",
+                avanza_account
+            ),
+            options.clone(),
+        )
+        .prompt()
+        .expect("Failed to get input");
+        if ans == CREATE_ACCOUNT {
+            let name = inquire::Text::new("Enter account name")
+                .with_default(avanza_account)
+                .prompt()
+                .expect("Failed to get input");
+            let currency = inquire::Text::new("Enter account currency")
+                .with_default("SEK")
+                .prompt()
+                .expect("Failed to get input");
+            return self.create_account(name, currency).await;
+        }
         let index = options.iter().position(|x| *x == ans).unwrap();
         accounts.accounts[index].id.clone()
     }
 
-    pub async fn get_account_mapping(&mut self, symbol: String) -> String {
-        if self.config.account_mapping.contains_key(&symbol) {
-            self.config.account_mapping.get(&symbol).unwrap().clone()
-        } else {
-            let account = inquire::Text::new("Enter account for symbol")
-                .prompt()
-                .expect("Failed to get input");
-            self.config.account_mapping.insert(symbol, account.clone());
-            self.full_config.save();
-            account
+    /// Ghostfolio account id for an Avanza account. Stored ids that no longer exist in
+    /// Ghostfolio are asked for again, new mappings are saved to the config right away unless
+    /// `dry_run`
+    pub async fn get_account_mapping(&mut self, avanza_account: String, dry_run: bool) -> String {
+        if let Some(account_id) = self.config().account_mapping.get(&avanza_account) {
+            let accounts = self.get_accounts().await;
+            if accounts
+                .accounts
+                .iter()
+                .any(|account| account.id == *account_id)
+            {
+                return account_id.clone();
+            }
+            println!(
+                "Mapped account {} for {} does not exist in Ghostfolio",
+                account_id, avanza_account
+            );
+        }
+        let account_id = self.select_account(&avanza_account, dry_run).await;
+        if dry_run {
+            return account_id;
         }
+        self.config_mut()
+            .account_mapping
+            .insert(avanza_account, account_id.clone());
+        self.save_config();
+        account_id
     }
 }
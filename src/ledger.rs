@@ -0,0 +1,42 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use crate::config::config_dir;
+use serde::{Deserialize, Serialize};
+
+/// Fingerprints of transaction rows that have already been imported to Ghostfolio, stored in
+/// `imported.json` next to `config.json`
+#[derive(Default, Serialize, Deserialize)]
+pub struct Ledger {
+    imported: BTreeSet<String>,
+}
+
+fn ledger_path() -> PathBuf {
+    config_dir().join("imported.json")
+}
+
+impl Ledger {
+    pub fn new() -> Ledger {
+        let ledger_path = ledger_path();
+        if ledger_path.exists() {
+            let ledger_file = std::fs::File::open(ledger_path).expect("Failed to open ledger file");
+            serde_json::from_reader(ledger_file).expect("Failed to parse ledger file")
+        } else {
+            Ledger::default()
+        }
+    }
+
+    pub fn contains(&self, fingerprint: &str) -> bool {
+        self.imported.contains(fingerprint)
+    }
+
+    pub fn insert(&mut self, fingerprint: String) {
+        self.imported.insert(fingerprint);
+    }
+
+    pub fn save(&self) {
+        let ledger_file =
+            std::fs::File::create(ledger_path()).expect("Failed to create ledger file");
+        serde_json::to_writer(ledger_file, &self).expect("Failed to write ledger file");
+    }
+}
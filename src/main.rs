@@ -1,21 +1,26 @@
+use crate::avanza::countries::iso_country_code;
 use crate::avanza::fund_info::get_avanza_fund_info;
-use crate::avanza::history::TimePeriod;
 use crate::avanza::search::Hit;
 use crate::avanza::stock_info::avanza_get_stock_info;
+use crate::config::Config;
+use crate::ghostfolio::{AssetProfile, AssetQuery, MarketData, MarketPrice};
 use avanza::transaction_history_parser;
 use chrono::NaiveDate;
 use clap::{Parser, Subcommand};
 use cli_clipboard::{ClipboardContext, ClipboardProvider};
-use inquire::{InquireError, Select};
-use log::debug;
+use inquire::Select;
 use serde::Serialize;
 use serde_json::{json, to_string, Value};
+use std::error::Error;
 use std::fmt;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 mod avanza;
 mod config;
 mod ghostfolio;
+mod ledger;
+mod profile_sync;
 
 #[derive(Serialize, PartialEq, Debug)]
 pub enum SymbolType {
@@ -31,12 +36,14 @@ impl fmt::Display for SymbolType {
         }
     }
 }
-impl SymbolType {
-    pub fn from_str(s: &str) -> Self {
+impl FromStr for SymbolType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "STOCK" => Self::STOCK,
-            "FUND" => Self::MUTUALFUND,
-            _ => panic!("Unknown symbol type"),
+            "STOCK" => Ok(Self::STOCK),
+            "FUND" => Ok(Self::MUTUALFUND),
+            _ => Err(format!("Unknown symbol type {}", s)),
         }
     }
 }
@@ -65,11 +72,37 @@ enum Commands {
         /// To, format: YYYY-MM-DD. Defaults to today
         #[arg(short, long)]
         to: Option<String>,
+
+        /// Store the history as Ghostfolio market data instead of copying it to the clipboard
+        #[arg(long)]
+        push: bool,
+
+        /// MANUAL symbol in Ghostfolio to store the history for. Defaults to the symbol mapped
+        /// to the Avanza instrument
+        #[arg(long, requires = "push")]
+        symbol: Option<String>,
+
+        /// Replace prices for dates Ghostfolio already has, these are skipped by default
+        #[arg(long, requires = "push")]
+        overwrite: bool,
     },
-    /// Parse transactions from a csv file from Avanza to later import to Avanza
+    /// Parse transactions from a csv file from Avanza and import them to Ghostfolio
     ParseTransactions {
         #[arg(short, long)]
         file: PathBuf,
+
+        /// Write a Ghostfolio import file to this path instead of importing directly
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Show what would be imported compared to the existing activities without writing anything
+        #[arg(long, conflicts_with = "output")]
+        dry_run: bool,
+
+        /// Set the cash balance of the accounts to the sum of the export, only correct when the
+        /// file covers the full account history
+        #[arg(long)]
+        sync_balances: bool,
     },
     /// Get scraper configuration for a symbol, used by Ghostfolio to scrape data
     GetScraperConfiguration {
@@ -79,10 +112,26 @@ enum Commands {
     GetSectors {
         name: String,
     },
-    /// Get countries in format [{name: "Sweden", weight: 0.5}, ...]
+    /// Get countries in format [{code: "SE", weight: 0.5}, ...]
     GetCountries {
         name: String,
     },
+    /// Update Ghostfolio asset profiles with name, sectors, countries and scraper configuration from Avanza
+    UpdateProfile {
+        /// Symbol to search for on Avanza
+        #[arg(required_unless_present = "all")]
+        name: Option<String>,
+
+        /// Update every MANUAL asset in Ghostfolio
+        #[arg(long, conflicts_with = "name")]
+        all: bool,
+    },
+    /// Refresh name, currency, sectors and countries of all Ghostfolio assets found on Avanza
+    SyncProfiles {
+        /// Only print what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
 
     Test,
 }
@@ -104,17 +153,41 @@ async fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Some(Commands::Import { name, from, to }) => {
+        Some(Commands::Import {
+            name,
+            from,
+            to,
+            push,
+            symbol,
+            overwrite,
+        }) => {
             println!("Importing history for {}", name);
-            import_history(
-                name,
+            let hit = find_symbol(name).await;
+            let history = import_history(
+                &hit,
                 from.unwrap_or(get_date_one_year_ago()),
                 to.unwrap_or(get_today()),
             )
             .await;
+            if push {
+                push_history(&hit, symbol, overwrite, history).await;
+            } else {
+                let mut csv_data: Vec<String> = vec!["date;marketPrice".to_string()];
+                csv_data.extend(
+                    history
+                        .iter()
+                        .map(|price| format!("{};{}", price.date, price.market_price)),
+                );
+                copy_to_clipboard(csv_data.join("\n"));
+            }
         }
-        Some(Commands::ParseTransactions { file }) => {
-            transaction_history_parser::parse_from_file(file).await
+        Some(Commands::ParseTransactions {
+            file,
+            output,
+            dry_run,
+            sync_balances,
+        }) => {
+            transaction_history_parser::parse_from_file(file, output, dry_run, sync_balances).await
         }
         Some(Commands::GetScraperConfiguration { name }) => {
             copy_to_clipboard(get_scraper_configuration(name).await)
@@ -122,7 +195,14 @@ async fn main() {
         Some(Commands::GetSectors { name }) => {
             copy_to_clipboard(get_sectors(find_symbol(name).await).await)
         }
-        Some(Commands::GetCountries { .. }) => {}
+        Some(Commands::GetCountries { name }) => {
+            copy_to_clipboard(get_countries(find_symbol(name).await).await)
+        }
+        Some(Commands::UpdateProfile { name, .. }) => match name {
+            Some(name) => update_profile(find_symbol(name).await).await,
+            None => update_manual_profiles().await,
+        },
+        Some(Commands::SyncProfiles { dry_run }) => profile_sync::sync_profiles(dry_run).await,
         Some(Commands::Test) => {
             let ghost = ghostfolio::GhostfolioApi::new();
             let assets = ghost.get_assets().await;
@@ -134,86 +214,84 @@ async fn main() {
     }
 }
 
-fn time_period_from(from: NaiveDate) -> TimePeriod {
-    let today: NaiveDate = chrono::offset::Local::now().naive_local().into();
-    let diff = today.signed_duration_since(from);
-    let days = diff.num_days();
-    if days < 30 {
-        TimePeriod::OneMonth
-    } else if days < 90 {
-        TimePeriod::ThreeMonths
-    } else if days < 365 {
-        TimePeriod::OneYear
-    } else if days < 365 * 3 {
-        TimePeriod::ThreeYears
-    } else if days < 365 * 5 {
-        TimePeriod::FiveYears
-    } else {
-        TimePeriod::Max
-    }
+async fn import_history(hit: &Hit, from: String, to: String) -> Vec<MarketPrice> {
+    let from = NaiveDate::parse_from_str(&from, "%Y-%m-%d").expect("Invalid from date");
+    let to = NaiveDate::parse_from_str(&to, "%Y-%m-%d").expect("Invalid to date");
+    // ETFs, certificates and other exchange traded instruments share the stock price chart
+    let symbol_type = SymbolType::from_str(&hit.link.type_field).unwrap_or(SymbolType::STOCK);
+    avanza::history::get_daily_prices(&symbol_type, &hit.link.orderbook_id, from, to)
+        .await
+        .expect("Failed to get history")
+        .into_iter()
+        .map(|price| MarketPrice {
+            date: price.date.format("%Y-%m-%d").to_string(),
+            market_price: price.price,
+        })
+        .collect()
 }
 
-async fn import_history(name: String, from: String, to: String) {
-    let to_timestamp = chrono::NaiveDate::parse_from_str(&to, "%Y-%m-%d").unwrap();
-    let from_timestamp = chrono::NaiveDate::parse_from_str(&from, "%Y-%m-%d").unwrap();
-    let hit = find_symbol(name).await;
-    let mut csv_data: Vec<String> = vec!["date;marketPrice".to_string()];
-    let mut time_period = time_period_from(to_timestamp);
-    let mut oldest_recorded_timestamp = i64::MAX;
-
-    loop {
-        println!("period: {}", time_period.to_str());
-        let history = avanza::history::get_history(&hit.link.orderbook_id, &time_period).await;
-        let start_timestamp = history.data_serie[0].timestamp;
-        for data in history.data_serie {
-            if data.timestamp > oldest_recorded_timestamp {
-                break;
-            }
-            let timestamp: chrono::NaiveDate =
-                chrono::NaiveDateTime::from_timestamp_millis(data.timestamp)
-                    .unwrap()
-                    .into();
-
-            if timestamp > to_timestamp {
-                break;
-            }
-            csv_data.push(format!(
-                "{};{}",
-                timestamp_to_date(data.timestamp),
-                data.price
-            ));
-        }
-        oldest_recorded_timestamp = start_timestamp;
-        if is_date_greater(&from, &history.from_date) {
-            break;
-        }
-        time_period = match time_period {
-            TimePeriod::OneMonth => TimePeriod::ThreeMonths,
-            TimePeriod::ThreeMonths => TimePeriod::OneYear,
-            TimePeriod::OneYear => TimePeriod::ThreeYears,
-            TimePeriod::ThreeYears => TimePeriod::FiveYears,
-            TimePeriod::FiveYears => TimePeriod::Max,
-            TimePeriod::Max => break,
-        };
+/// Stores the history as market data of a MANUAL asset in Ghostfolio
+async fn push_history(
+    hit: &Hit,
+    symbol: Option<String>,
+    overwrite: bool,
+    history: Vec<MarketPrice>,
+) {
+    let ghostfolio = ghostfolio::GhostfolioApi::new();
+    let config = ghostfolio.full_config();
+    let orderbook_id = &hit.link.orderbook_id;
+    let symbol = symbol.or_else(|| {
+        let data_source = config
+            .avanza_to_ghostfolio_data_source
+            .get(orderbook_id)
+            .map_or("MANUAL", |x| x.as_str());
+        (data_source == "MANUAL")
+            .then(|| {
+                config
+                    .avanza_to_ghostfolio_ticker
+                    .get(orderbook_id)
+                    .cloned()
+            })
+            .flatten()
+    });
+    let Some(symbol) = symbol else {
+        println!(
+            "No MANUAL symbol mapped for {}, use --symbol",
+            hit.link.link_display
+        );
+        return;
+    };
+    let total = history.len();
+    let history = if overwrite {
+        history
+    } else {
+        let existing = ghostfolio
+            .get_market_data_dates("MANUAL", &symbol)
+            .await
+            .expect("Failed to get market data");
+        history
+            .into_iter()
+            .filter(|price| !existing.contains(&price.date))
+            .collect()
+    };
+    match ghostfolio
+        .update_market_data("MANUAL", &symbol, &history)
+        .await
+    {
+        Ok(stored) => println!(
+            "Stored {} prices for {}, skipped {} existing dates",
+            stored,
+            symbol,
+            total - history.len()
+        ),
+        Err(e) => println!("Failed to store prices for {}: {}", symbol, e),
     }
-
-    copy_to_clipboard(csv_data.join("\n"));
-}
-
-fn is_date_greater(first: &String, second: &String) -> bool {
-    let first_date = chrono::NaiveDate::parse_from_str(first, "%Y-%m-%d").unwrap();
-    let second_date = chrono::NaiveDate::parse_from_str(second, "%Y-%m-%d").unwrap();
-    first_date > second_date
-}
-fn timestamp_to_date(timestamp: i64) -> String {
-    let naive = chrono::NaiveDateTime::from_timestamp_millis(timestamp).unwrap();
-    naive.format("%Y-%m-%d").to_string()
 }
 
 async fn find_symbol(name: String) -> Hit {
     let hits = avanza::search::search_avanza(&name).await.unwrap();
     let options = hits.iter().map(format_hit).collect::<Vec<String>>();
-    if options.len() == 0 {
+    if options.is_empty() {
         panic!("No hits found");
     }
     if options.len() == 1 {
@@ -227,10 +305,6 @@ async fn find_symbol(name: String) -> Hit {
     hits[index].clone().clone()
 }
 
-async fn create_symbol(name: String) {
-    let hit = find_symbol(name).await;
-}
-
 fn format_hit(hit: &Hit) -> String {
     format!(
         "{} - {} ({} {})",
@@ -239,29 +313,197 @@ fn format_hit(hit: &Hit) -> String {
 }
 async fn get_scraper_configuration(name: String) -> String {
     let symbol = find_symbol(name).await;
-    let url = if symbol.link.type_field == SymbolType::STOCK.to_string() {
-        format!(
-            "https://www.avanza.se/_api/market-guide/stock/{}",
-            symbol.link.orderbook_id
-        )
-    } else {
-        format!(
-            "https://www.avanza.se/_api/fund-guide/guide/{}",
-            symbol.link.orderbook_id
-        )
-    };
-    let selector = if symbol.link.type_field == SymbolType::STOCK.to_string() {
-        "$.quote.last"
-    } else {
-        "$.nav"
+    let symbol_type = SymbolType::from_str(&symbol.link.type_field).unwrap();
+    to_string(&scraper_configuration(
+        &symbol_type,
+        &symbol.link.orderbook_id,
+    ))
+    .expect("Failed to serialize")
+}
+
+fn scraper_configuration(symbol_type: &SymbolType, orderbook_id: &str) -> Value {
+    let (url, selector) = match symbol_type {
+        SymbolType::STOCK => (
+            format!(
+                "https://www.avanza.se/_api/market-guide/stock/{}",
+                orderbook_id
+            ),
+            "$.quote.last",
+        ),
+        SymbolType::MUTUALFUND => (
+            format!(
+                "https://www.avanza.se/_api/fund-guide/guide/{}",
+                orderbook_id
+            ),
+            "$.nav",
+        ),
     };
-    let config = json!({
+    json!({
         "url": url,
         "selector": selector,
 
-    });
-    to_string(&config).expect("Failed to serialize")
+    })
 }
+
+/// Builds a Ghostfolio asset profile, including sectors and countries, from Avanza's data
+async fn asset_profile(
+    symbol_type: &SymbolType,
+    orderbook_id: &str,
+) -> Result<AssetProfile, Box<dyn Error>> {
+    let (name, currency, asset_sub_class, sectors, countries) = match symbol_type {
+        SymbolType::STOCK => {
+            let stock_info = avanza_get_stock_info(orderbook_id).await?;
+            let sectors = stock_info
+                .sectors
+                .first()
+                .map(|x| avanza_to_ghostfolio_weights(&x.sector_name, 100.0))
+                .into_iter()
+                .collect::<Vec<Value>>();
+            let countries = iso_country_code("", &stock_info.listing.country_code)
+                .map(|code| (code, 100.0))
+                .into_iter()
+                .collect::<Vec<(String, f64)>>();
+            (
+                stock_info.name,
+                stock_info.listing.currency,
+                "STOCK",
+                sectors,
+                countries,
+            )
+        }
+        SymbolType::MUTUALFUND => {
+            let fund_info = get_avanza_fund_info(&orderbook_id.to_string()).await?;
+            let sectors = fund_info
+                .sector_chart_data
+                .iter()
+                .map(|x| avanza_to_ghostfolio_weights(&x.name, x.y))
+                .collect::<Vec<Value>>();
+            let countries = fund_info
+                .country_chart_data
+                .iter()
+                .filter_map(|x| {
+                    let code = iso_country_code(&x.name, &x.country_code);
+                    if code.is_none() {
+                        println!("Skipping unknown country {}", x.name);
+                    }
+                    code.map(|code| (code, x.y))
+                })
+                .collect::<Vec<(String, f64)>>();
+            (
+                fund_info.name,
+                fund_info.currency,
+                "MUTUALFUND",
+                sectors,
+                countries,
+            )
+        }
+    };
+    let countries = normalize_weights(countries)
+        .into_iter()
+        .map(|(code, weight)| json!({ "code": code, "weight": weight }))
+        .collect::<Vec<Value>>();
+    Ok(AssetProfile {
+        asset_class: Some("EQUITY".to_string()),
+        asset_sub_class: Some(asset_sub_class.to_string()),
+        countries: Some(countries),
+        currency: Some(currency),
+        isin: None,
+        name: Some(name),
+        scraper_configuration: Some(scraper_configuration(symbol_type, orderbook_id)),
+        sectors: Some(sectors),
+    })
+}
+
+async fn hit_asset_profile(hit: &Hit) -> Result<AssetProfile, Box<dyn Error>> {
+    let symbol_type = SymbolType::from_str(&hit.link.type_field)?;
+    asset_profile(&symbol_type, &hit.link.orderbook_id).await
+}
+
+async fn update_profile(hit: Hit) {
+    let ghostfolio = ghostfolio::GhostfolioApi::new();
+    let config = ghostfolio.full_config();
+    let orderbook_id = &hit.link.orderbook_id;
+    let mapped = config
+        .avanza_to_ghostfolio_ticker
+        .get(orderbook_id)
+        .map(|symbol| {
+            let data_source = config
+                .avanza_to_ghostfolio_data_source
+                .get(orderbook_id)
+                .map_or("MANUAL", |x| x.as_str());
+            (data_source.to_string(), symbol.clone())
+        });
+    let asset = match mapped {
+        Some((data_source, symbol)) => ghostfolio
+            .query_assets(&AssetQuery {
+                data_source: Some(data_source.clone()),
+                query: Some(symbol.clone()),
+                ..Default::default()
+            })
+            .await
+            .into_iter()
+            .find(|asset| asset.data_source == data_source && asset.symbol == symbol),
+        None => None,
+    };
+    let asset = match asset {
+        Some(asset) => asset,
+        None => ghostfolio.select_asset().await,
+    };
+    let mut profile = hit_asset_profile(&hit)
+        .await
+        .expect("Failed to get asset profile");
+    if asset.data_source != "MANUAL" {
+        // Ghostfolio only scrapes MANUAL assets
+        profile.scraper_configuration = None;
+    }
+    match ghostfolio.update_assets(&asset, &profile).await {
+        Ok(()) => println!("Updated {} {}", asset.data_source, asset.symbol),
+        Err(e) => println!("Failed to update {}: {}", asset.symbol, e),
+    }
+}
+
+async fn update_manual_profiles() {
+    let ghostfolio = ghostfolio::GhostfolioApi::new();
+    for asset in ghostfolio
+        .query_assets(&AssetQuery {
+            data_source: Some("MANUAL".to_string()),
+            ..Default::default()
+        })
+        .await
+    {
+        let Some((symbol_type, orderbook_id)) = avanza_instrument(ghostfolio.full_config(), &asset)
+        else {
+            println!("Skipping {}, no Avanza instrument known", asset.symbol);
+            continue;
+        };
+        let result = match asset_profile(&symbol_type, &orderbook_id).await {
+            Ok(profile) => ghostfolio.update_assets(&asset, &profile).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => println!("Updated {}", asset.symbol),
+            Err(e) => println!("Failed to update {}: {}", asset.symbol, e),
+        }
+    }
+}
+
+/// The Avanza instrument behind a Ghostfolio asset, either from the config mapping or, for
+/// MANUAL assets created by this tool, the orderbook id used as symbol
+fn avanza_instrument(config: &Config, asset: &MarketData) -> Option<(SymbolType, String)> {
+    let symbol_type = match asset.asset_sub_class.as_str() {
+        "STOCK" => SymbolType::STOCK,
+        "MUTUALFUND" => SymbolType::MUTUALFUND,
+        _ => return None,
+    };
+    let orderbook_id = config
+        .orderbook_id(&asset.data_source, &asset.symbol)
+        .or_else(|| {
+            (asset.data_source == "MANUAL" && asset.symbol.chars().all(|c| c.is_ascii_digit()))
+                .then(|| asset.symbol.clone())
+        })?;
+    Some((symbol_type, orderbook_id))
+}
+
 fn copy_to_clipboard(s: String) {
     let mut ctx = ClipboardContext::new().unwrap();
     ctx.set_contents(s.clone()).unwrap_or_else(|e| {
@@ -272,35 +514,27 @@ fn copy_to_clipboard(s: String) {
 }
 
 async fn get_sectors(hit: Hit) -> String {
-    let sectors = match SymbolType::from_str(&hit.link.type_field) {
-        SymbolType::STOCK => {
-            panic!("Stock not supported");
-        }
-        SymbolType::MUTUALFUND => get_avanza_fund_info(&hit.link.orderbook_id)
-            .await
-            .unwrap()
-            .sector_chart_data
-            .iter()
-            .map(|x| avanza_to_ghostfolio_weights(&x.name, x.y))
-            .collect::<Vec<Value>>(),
-    };
-    to_string(&sectors).unwrap()
+    to_string(&hit_asset_profile(&hit).await.unwrap().sectors).unwrap()
 }
 
-async fn get_countries(hit: Hit) {
-    let countries = match SymbolType::from_str(&hit.link.type_field) {
-        SymbolType::STOCK => {
-            panic!("Stock not supported");
+async fn get_countries(hit: Hit) -> String {
+    to_string(&hit_asset_profile(&hit).await.unwrap().countries).unwrap()
+}
+
+/// Merges duplicate names and scales the weights so that they sum to 1
+fn normalize_weights(weights: Vec<(String, f64)>) -> Vec<(String, f64)> {
+    let mut merged: Vec<(String, f64)> = Vec::new();
+    for (name, weight) in weights {
+        match merged.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, w)) => *w += weight,
+            None => merged.push((name, weight)),
         }
-        SymbolType::MUTUALFUND => get_avanza_fund_info(&hit.link.orderbook_id)
-            .await
-            .unwrap()
-            .country_chart_data
-            .iter()
-            .map(|x| avanza_to_ghostfolio_weights(&x.name, x.y))
-            .collect::<Vec<Value>>(),
-    };
-    copy_to_clipboard(to_string(&countries).unwrap());
+    }
+    let total: f64 = merged.iter().map(|(_, weight)| weight).sum();
+    if total > 0.0 {
+        merged.iter_mut().for_each(|(_, weight)| *weight /= total);
+    }
+    merged
 }
 
 fn avanza_to_ghostfolio_weights(name: &String, y: f64) -> Value {
@@ -0,0 +1,171 @@
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::avanza::search::search_avanza;
+use crate::avanza::symbol_resolver::select_hit;
+use crate::ghostfolio::{AssetProfile, GhostfolioApi, MarketData};
+use crate::{asset_profile, avanza_instrument, SymbolType};
+
+/// Refreshes name, currency, sectors and countries of every Ghostfolio asset that can be traced
+/// back to an Avanza instrument and prints what changed per asset
+pub async fn sync_profiles(dry_run: bool) {
+    let mut ghostfolio = GhostfolioApi::new();
+    let assets = ghostfolio.get_assets().await;
+    let mut changed = 0;
+    for asset in &assets {
+        let current = match ghostfolio
+            .get_asset_profile(&asset.data_source, &asset.symbol)
+            .await
+        {
+            Ok(profile) => profile,
+            Err(e) => {
+                println!("{}: failed to get asset profile: {}", asset.symbol, e);
+                continue;
+            }
+        };
+        let Some((symbol_type, orderbook_id)) =
+            resolve_instrument(&mut ghostfolio, asset, &current, dry_run).await
+        else {
+            println!("{}: skipped, no Avanza instrument found", asset.symbol);
+            continue;
+        };
+        let refreshed = match asset_profile(&symbol_type, &orderbook_id).await {
+            Ok(profile) => profile,
+            Err(e) => {
+                println!("{}: failed to get Avanza data: {}", asset.symbol, e);
+                continue;
+            }
+        };
+        let profile = AssetProfile {
+            countries: refreshed.countries,
+            currency: refreshed.currency,
+            name: refreshed.name,
+            sectors: refreshed.sectors,
+            ..Default::default()
+        };
+        let changes = changes(&current, &profile);
+        if changes.is_empty() {
+            println!("{}: unchanged", asset.symbol);
+            continue;
+        }
+        println!("{}:", asset.symbol);
+        for change in &changes {
+            println!("  {}", change);
+        }
+        if dry_run {
+            changed += 1;
+            continue;
+        }
+        match ghostfolio.update_assets(asset, &profile).await {
+            Ok(()) => changed += 1,
+            Err(e) => println!("  Failed to update: {}", e),
+        }
+    }
+    if dry_run {
+        println!("Would update {} of {} assets", changed, assets.len());
+    } else {
+        // Instruments found through ISIN search are remembered for the next sync
+        ghostfolio.save_config();
+        println!("Updated {} of {} assets", changed, assets.len());
+    }
+}
+
+/// Finds the Avanza instrument through the config mapping or, failing that, the asset's ISIN.
+/// Instruments found by ISIN are added to the mapping unless `dry_run`
+async fn resolve_instrument(
+    ghostfolio: &mut GhostfolioApi,
+    asset: &MarketData,
+    current: &AssetProfile,
+    dry_run: bool,
+) -> Option<(SymbolType, String)> {
+    if let Some(instrument) = avanza_instrument(ghostfolio.full_config(), asset) {
+        return Some(instrument);
+    }
+    let isin = current.isin.as_ref()?;
+    let hits = search_avanza(isin)
+        .await
+        .ok()?
+        .into_iter()
+        .filter(|hit| SymbolType::from_str(&hit.link.type_field).is_ok())
+        .collect::<Vec<_>>();
+    if hits.is_empty() {
+        return None;
+    }
+    let hit = select_hit(ghostfolio.full_config(), isin, &hits).clone();
+    let orderbook_id = hit.link.orderbook_id;
+    let symbol_type = SymbolType::from_str(&hit.link.type_field).ok()?;
+    if dry_run {
+        return Some((symbol_type, orderbook_id));
+    }
+    let config = ghostfolio.full_config_mut();
+    config
+        .avanza_to_ghostfolio_ticker
+        .insert(orderbook_id.clone(), asset.symbol.clone());
+    config
+        .avanza_to_ghostfolio_data_source
+        .insert(orderbook_id.clone(), asset.data_source.clone());
+    Some((symbol_type, orderbook_id))
+}
+
+fn changes(current: &AssetProfile, refreshed: &AssetProfile) -> Vec<String> {
+    let fields = [
+        ("Name", current.name.clone(), refreshed.name.clone()),
+        (
+            "Currency",
+            current.currency.clone(),
+            refreshed.currency.clone(),
+        ),
+        (
+            "Sectors",
+            current
+                .sectors
+                .as_deref()
+                .map(|x| format_weights(x, "name")),
+            refreshed
+                .sectors
+                .as_deref()
+                .map(|x| format_weights(x, "name")),
+        ),
+        (
+            "Countries",
+            current
+                .countries
+                .as_deref()
+                .map(|x| format_weights(x, "code")),
+            refreshed
+                .countries
+                .as_deref()
+                .map(|x| format_weights(x, "code")),
+        ),
+    ];
+    fields
+        .into_iter()
+        .filter(|(_, old, new)| {
+            old.as_deref().unwrap_or_default() != new.as_deref().unwrap_or_default()
+        })
+        .map(|(field, old, new)| {
+            format!(
+                "{}: {} -> {}",
+                field,
+                old.unwrap_or_default(),
+                new.unwrap_or_default()
+            )
+        })
+        .collect()
+}
+
+/// Formats weights as e.g. `SE 50.0%, US 50.0%`, rounded so that float noise is not a change
+fn format_weights(weights: &[Value], key: &str) -> String {
+    weights
+        .iter()
+        .map(|x| {
+            format!(
+                "{} {:.1}%",
+                x[key].as_str().unwrap_or_default(),
+                x["weight"].as_f64().unwrap_or_default() * 100.0
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}